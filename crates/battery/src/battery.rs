@@ -1,25 +1,38 @@
-use std::{fs, path::PathBuf};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::Serialize;
 
 type Result<T, E = Box<dyn std::error::Error + Send>> = std::result::Result<T, E>;
 
-#[derive(Clone, Default, Debug)]
+#[derive(Clone, Default, Debug, Serialize)]
 pub struct Battery {
     pub root: PathBuf,
 
     state: State,
     soc: f32,
+    /// Remaining energy in Wh.
+    energy: f32,
+    /// Energy when full in Wh.
+    energy_full: f32,
+    /// Charge or discharge rate in W.
+    rate: f32,
 }
 
 impl Battery {
-    pub fn with_root(root: PathBuf) -> Self {
+    /// Read the battery at `root`, e.g. `/sys/class/power_supply/BAT0`.
+    pub fn with_root(root: PathBuf) -> Result<Self> {
         let mut battery = Self {
             root,
             ..Default::default()
         };
 
-        battery.read().unwrap();
+        battery.read()?;
 
-        battery
+        Ok(battery)
     }
 
     /// Get current battery state.
@@ -42,27 +55,99 @@ impl Battery {
         self.soc
     }
 
+    /// Get remaining energy in Wh.
+    pub fn energy(&self) -> f32 {
+        self.energy
+    }
+
+    /// Get energy when full in Wh.
+    pub fn energy_full(&self) -> f32 {
+        self.energy_full
+    }
+
+    /// Get current charge or discharge rate in W.
+    pub fn energy_rate(&self) -> f32 {
+        self.rate
+    }
+
+    /// Get estimated time until the battery is empty, or full while charging.
+    pub fn time_left(&self) -> Option<Duration> {
+        if self.rate <= 0.0 {
+            return None;
+        }
+
+        let hours = match self.state {
+            State::Charging => (self.energy_full - self.energy) / self.rate,
+            State::Discharging => self.energy / self.rate,
+            _ => return None,
+        };
+
+        Some(Duration::from_secs_f32(hours.max(0.0) * 3600.0))
+    }
+
     /// Refresh battery data
     pub fn refresh(&mut self) -> Result<&Self> {
         self.read()
     }
 
+    /// Read the state of the battery. Fails if its capacity or status can't be
+    /// read, the energy is optional and defaults to zero.
     fn read(&mut self) -> Result<&Self> {
-        self.soc = read_file(&self.root.join("capacity"))
-            .unwrap()
-            .parse()
-            .unwrap_or(100.0);
+        let capacity = read_file(&self.root.join("capacity"))?;
+        self.soc = capacity.trim().parse().map_err(|e| {
+            Box::new(Error(format!(
+                "Invalid capacity '{}': {}",
+                capacity.trim(),
+                e
+            ))) as _
+        })?;
+        self.state = State::from(read_file(&self.root.join("status"))?.trim());
+
+        // Energy is reported in µWh and power in µW. Some batteries only
+        // report charge in µAh and current in µA instead.
+        let voltage = self.read_value("voltage_now").map(|voltage| voltage / 1e6);
+        let read = |energy: &str, charge: &str| {
+            self.read_value(energy)
+                .or_else(|| Some(self.read_value(charge)? * voltage?))
+                .map(|value| value / 1e6)
+        };
+
+        let energy = read("energy_now", "charge_now");
+        let energy_full = read("energy_full", "charge_full");
+        let rate = read("power_now", "current_now");
+
+        self.energy = energy.unwrap_or_default();
+        self.energy_full = energy_full.unwrap_or_default();
+        self.rate = rate.unwrap_or_default().abs();
 
         Ok(self)
     }
+
+    fn read_value(&self, name: &str) -> Option<f32> {
+        read_file(&self.root.join(name)).ok()?.trim().parse().ok()
+    }
 }
 
-fn read_file(path: &PathBuf) -> Result<String, Box<dyn std::error::Error>> {
-    fs::read_to_string(path).map_err(|e| e.into())
+fn read_file(path: &Path) -> Result<String> {
+    fs::read_to_string(path)
+        .map_err(|e| Box::new(Error(format!("Failed to read '{}': {}", path.display(), e))) as _)
 }
 
+/// [Error] reading a battery.
+#[derive(Debug)]
+pub struct Error(String);
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
 /// Battery state.
-#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum State {
     Charging,
     Discharging,
@@ -70,3 +155,15 @@ pub enum State {
     #[default]
     Unknown,
 }
+
+impl From<&str> for State {
+    /// Parse the `status` reported by the kernel.
+    fn from(status: &str) -> Self {
+        match status {
+            "Charging" => Self::Charging,
+            "Discharging" => Self::Discharging,
+            "Full" => Self::Full,
+            _ => Self::Unknown,
+        }
+    }
+}
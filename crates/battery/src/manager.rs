@@ -8,6 +8,12 @@ pub struct Manager {
 
 type Result<T, E = Box<dyn std::error::Error>> = std::result::Result<T, E>;
 
+impl Default for Manager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Manager {
     pub fn new() -> Self {
         Self {
@@ -17,6 +23,18 @@ impl Manager {
 
     /// Get all batteries.
     pub fn batteries(&self) -> Result<Vec<Battery>> {
+        Ok(self
+            .paths()?
+            .filter_map(|p| read_battery(p).ok())
+            .collect::<Vec<_>>())
+    }
+
+    /// Check if there is a battery, without reading it.
+    pub fn has_batteries(&self) -> bool {
+        self.paths().is_ok_and(|mut paths| paths.next().is_some())
+    }
+
+    fn paths(&self) -> Result<impl Iterator<Item = PathBuf>> {
         Ok(fs::read_dir(&self.root)?
             .filter_map(|e| e.ok())
             .map(|e| e.path())
@@ -25,13 +43,11 @@ impl Manager {
                     .and_then(|n| n.to_str())
                     .unwrap_or("")
                     .starts_with("BAT")
-            })
-            .filter_map(|p| read_battery(p).ok())
-            .collect::<Vec<_>>())
+            }))
     }
 }
 
 fn read_battery(path: PathBuf) -> Result<Battery> {
     println!("Reading battery at {:?}", path);
-    Ok(Battery::with_root(path))
+    Battery::with_root(path).map_err(|e| e as _)
 }
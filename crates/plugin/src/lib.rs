@@ -0,0 +1,225 @@
+//! ABI of rbar plugins.
+//!
+//! A plugin is a `cdylib` placed in `~/.config/rbar/plugins`. It exports a
+//! [Declaration] named `rbar_plugin_declaration`, usually with [declare_plugin],
+//! and is used in the config like any other module, with its name as `name`:
+//!
+//! ```json
+//! { "name": "weather", "config": { "position": "right" }, "city": "Berlin" }
+//! ```
+//!
+//! Everything crossing the boundary is plain C: strings are nul-terminated UTF-8,
+//! options and state are JSON, and widgets are `GtkWidget` pointers, so plugins
+//! don't have to be built with the same compiler as rbar. Panics don't cross
+//! it either: the functions of [declare_plugin] turn them into errors.
+
+use std::{
+    any::Any,
+    ffi::{c_char, c_void, CStr, CString},
+    panic::{self, AssertUnwindSafe},
+    ptr,
+};
+
+/// Version of the ABI. rbar refuses to load plugins declaring another version.
+pub const ABI_VERSION: u32 = 1;
+
+/// Name of the symbol the [Declaration] is exported as.
+pub const DECLARATION_SYMBOL: &CStr = c"rbar_plugin_declaration";
+
+/// [Declaration] describes a plugin module.
+#[repr(C)]
+pub struct Declaration {
+    /// Must be [ABI_VERSION].
+    pub abi_version: u32,
+    /// Name of the module, used as `name` in the config.
+    pub name: *const c_char,
+    /// JSON schema of the options of the module, or null.
+    pub config_schema: *const c_char,
+
+    /// Create a module from its options as JSON.
+    ///
+    /// Returns null and sets `error` if the options are invalid.
+    pub create: unsafe extern "C" fn(
+        options: *const c_char,
+        host: Host,
+        error: *mut *mut c_char,
+    ) -> *mut c_void,
+    /// Create the widget of the module. Returns a new reference to a `GtkWidget`.
+    ///
+    /// Called on the GTK main thread.
+    pub widget: unsafe extern "C" fn(module: *mut c_void) -> *mut c_void,
+    /// Run a module-defined action. Returns `false` and sets `error` on failure.
+    pub action: unsafe extern "C" fn(
+        module: *mut c_void,
+        name: *const c_char,
+        error: *mut *mut c_char,
+    ) -> bool,
+    /// Destroy the module once it is stopped.
+    pub destroy: unsafe extern "C" fn(module: *mut c_void),
+    /// Free a string returned by the plugin, like an error.
+    pub free_string: unsafe extern "C" fn(string: *mut c_char),
+}
+
+// The declaration only points to static data and functions.
+unsafe impl Sync for Declaration {}
+
+/// [Host] lets a module talk to rbar. It can be used from any thread.
+///
+/// The host belongs to the module it was created with and is freed once the
+/// module is destroyed. It must not be used after `destroy` returned, so threads
+/// of the module using it have to be stopped when it is dropped.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Host {
+    pub data: *mut c_void,
+    /// Publish the state of the module as JSON, used for tooltips, `visible_when` and IPC.
+    pub update: unsafe extern "C" fn(data: *mut c_void, state: *const c_char),
+    /// Report an error, shown in the error state of the module.
+    pub error: unsafe extern "C" fn(data: *mut c_void, message: *const c_char),
+}
+
+unsafe impl Send for Host {}
+unsafe impl Sync for Host {}
+
+impl Host {
+    /// Publish the state of the module as JSON.
+    pub fn update(&self, state: &str) {
+        if let Ok(state) = CString::new(state) {
+            unsafe { (self.update)(self.data, state.as_ptr()) }
+        }
+    }
+
+    /// Report an error.
+    pub fn error(&self, message: &str) {
+        if let Ok(message) = CString::new(message) {
+            unsafe { (self.error)(self.data, message.as_ptr()) }
+        }
+    }
+}
+
+/// [Plugin] is the safe side of a [Declaration], implemented by plugin modules.
+pub trait Plugin: Sized + 'static {
+    /// Create the module from its options as JSON.
+    fn create(options: &str, host: Host) -> Result<Self, String>;
+
+    /// Create the widget, e.g. with gtk-rs: `widget.upcast::<gtk::Widget>().to_glib_full()`.
+    fn widget(&mut self) -> *mut c_void;
+
+    /// Run a module-defined action.
+    fn action(&mut self, name: &str) -> Result<(), String> {
+        Err(format!("No action '{}'", name))
+    }
+}
+
+/// Export a [Plugin] as `rbar_plugin_declaration`.
+///
+/// ```ignore
+/// rbar_plugin::declare_plugin!(Weather, name: c"weather", schema: Some(c"{\"required\": [\"city\"]}"));
+/// ```
+#[macro_export]
+macro_rules! declare_plugin {
+    ($plugin:ty, name: $name:expr) => {
+        $crate::declare_plugin!($plugin, name: $name, schema: None);
+    };
+    ($plugin:ty, name: $name:expr, schema: $schema:expr) => {
+        #[no_mangle]
+        pub static rbar_plugin_declaration: $crate::Declaration = $crate::Declaration {
+            abi_version: $crate::ABI_VERSION,
+            name: $crate::optional_ptr(Some($name)),
+            config_schema: $crate::optional_ptr($schema),
+            create: $crate::create::<$plugin>,
+            widget: $crate::widget::<$plugin>,
+            action: $crate::action::<$plugin>,
+            destroy: $crate::destroy::<$plugin>,
+            free_string: $crate::free_string,
+        };
+    };
+}
+
+/// Pointer to an optional static C string, used by [declare_plugin].
+#[doc(hidden)]
+pub const fn optional_ptr(string: Option<&'static CStr>) -> *const c_char {
+    match string {
+        Some(string) => string.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+#[doc(hidden)]
+pub unsafe extern "C" fn create<P: Plugin>(
+    options: *const c_char,
+    host: Host,
+    error: *mut *mut c_char,
+) -> *mut c_void {
+    let options = CStr::from_ptr(options).to_string_lossy();
+
+    match catch(|| P::create(&options, host)) {
+        Ok(plugin) => Box::into_raw(Box::new(plugin)).cast(),
+        Err(e) => {
+            set_error(error, &e);
+            ptr::null_mut()
+        }
+    }
+}
+
+#[doc(hidden)]
+pub unsafe extern "C" fn widget<P: Plugin>(module: *mut c_void) -> *mut c_void {
+    // rbar reports a missing widget as an error of the module.
+    catch(|| Ok((*module.cast::<P>()).widget())).unwrap_or(ptr::null_mut())
+}
+
+#[doc(hidden)]
+pub unsafe extern "C" fn action<P: Plugin>(
+    module: *mut c_void,
+    name: *const c_char,
+    error: *mut *mut c_char,
+) -> bool {
+    let name = CStr::from_ptr(name).to_string_lossy();
+
+    match catch(|| (*module.cast::<P>()).action(&name)) {
+        Ok(()) => true,
+        Err(e) => {
+            set_error(error, &e);
+            false
+        }
+    }
+}
+
+#[doc(hidden)]
+pub unsafe extern "C" fn destroy<P: Plugin>(module: *mut c_void) {
+    // Nobody to report to, the module is gone either way.
+    let _ = catch(|| {
+        drop(Box::from_raw(module.cast::<P>()));
+        Ok(())
+    });
+}
+
+#[doc(hidden)]
+pub unsafe extern "C" fn free_string(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+unsafe fn set_error(error: *mut *mut c_char, message: &str) {
+    if !error.is_null() {
+        let message = CString::new(message.replace('\0', "")).unwrap_or_default();
+        *error = message.into_raw();
+    }
+}
+
+/// Run a function of the plugin, returning a panic as an error instead of
+/// unwinding into rbar.
+fn catch<T>(f: impl FnOnce() -> Result<T, String>) -> Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|panic| Err(panic_message(&*panic)))
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    let message = panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic");
+
+    format!("Plugin panicked: {}", message)
+}
@@ -8,13 +8,18 @@ use tracing::{debug, error};
 
 use std::{env, sync::Arc};
 
-use crate::{modules::ModuleFactory, rbar::RBar};
+use crate::{
+    modules::{ModuleFactory, ModuleHandle, Modules},
+    rbar::RBar,
+};
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
 #[derive(Debug)]
 pub struct Bar {
     name: &'static str,
+    /// Name of the output the bar is shown on, e.g. `eDP-1`.
+    output: String,
 
     window: ApplicationWindow,
 
@@ -23,17 +28,24 @@ pub struct Bar {
     pub right: gtk::Box,
 
     pub rbar: Arc<RBar>,
+
+    modules: Vec<ModuleHandle>,
+    /// Whether updates are paused, e.g. while the system sleeps.
+    paused: bool,
 }
 
 impl Bar {
     /// Create a new bar.
     pub fn create(app: &Application, rbar: Arc<RBar>, monitor: &Monitor) -> Result<Self> {
-        let bar = Bar::new(app, rbar);
+        let bar = Bar::new(app, rbar, monitor);
         bar.init(monitor)
     }
 
-    fn new(app: &Application, rbar: Arc<RBar>) -> Self {
+    fn new(app: &Application, rbar: Arc<RBar>, monitor: &Monitor) -> Self {
         let name = "rbar";
+        let output = monitor
+            .connector()
+            .map_or_else(|| "unknown".to_string(), |connector| connector.to_string());
 
         let window = ApplicationWindow::builder().application(app).build();
         window.init_layer_shell();
@@ -66,19 +78,24 @@ impl Bar {
 
         Self {
             name,
+            output,
             window,
             rbar,
 
             left,
             center,
             right,
+
+            modules: Vec::new(),
+            paused: false,
         }
     }
 
-    pub fn init(self, monitor: &Monitor) -> Result<Self> {
+    pub fn init(mut self, monitor: &Monitor) -> Result<Self> {
         debug!(
-            "Initializing bar '{}' on {:?}",
+            "Initializing bar '{}' on {} ({:?})",
             self.name,
+            self.output,
             monitor.manufacturer()
         );
 
@@ -116,12 +133,58 @@ impl Bar {
         self.window.show();
     }
 
-    fn load_modules(&self) -> Result<()> {
-        let factory = ModuleFactory::new(self.rbar.clone());
+    /// Show the bar if it is hidden, hide it otherwise.
+    pub fn toggle(&self) {
+        self.window.set_visible(!self.window.is_visible());
+        self.update_modules();
+    }
+
+    /// Pause or resume the modules of the bar, e.g. while the system sleeps.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.update_modules();
+    }
+
+    /// Only run modules while the bar is shown and not paused.
+    fn update_modules(&self) {
+        let running = !self.paused && self.window.is_visible();
+
+        for module in &self.modules {
+            if running {
+                module.resume();
+            } else {
+                module.pause();
+            }
+        }
+    }
+
+    /// Close the bar and destroy its window.
+    pub fn close(&self) {
+        debug!("Closing bar '{}' on {}", self.name, self.output);
+        for module in &self.modules {
+            module.stop();
+        }
+        self.window.destroy();
+    }
+
+    /// Get name of the output the bar is shown on.
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+
+    /// Get all modules loaded on this bar.
+    pub fn modules(&self) -> &[ModuleHandle] {
+        &self.modules
+    }
+
+    fn load_modules(&mut self) -> Result<()> {
+        let rbar = self.rbar.clone();
+        let factory = ModuleFactory::new(rbar.clone());
 
-        for module in self.rbar.config.bar.modules.iter() {
-            if let Err(e) = module.create(&factory, self) {
-                error!("Failed to load module: {}", e);
+        for module in Modules::sorted(&rbar.config.bar.modules) {
+            match module.create(&factory, self) {
+                Ok(handles) => self.modules.extend(handles),
+                Err(e) => error!("Failed to load module: {}", e),
             }
         }
 
@@ -145,13 +208,14 @@ fn create_container(name: &str) -> gtk::Box {
 fn get_display() -> Display {
     use std::process::exit;
 
-    Display::default().map_or_else(|| exit(3), |display| display)
+    Display::default().unwrap_or_else(|| exit(3))
 }
 
-pub fn load_bars(rbar: Arc<RBar>, app: &Application) -> Result<()> {
+pub fn load_bars(rbar: Arc<RBar>, app: &Application) -> Result<Vec<Bar>> {
     let display = get_display();
 
     let monitors = display.monitors();
+    let mut bars = Vec::new();
 
     for i in 0..monitors.n_items() {
         let monitor = monitors.item(i).expect("monitor to exist");
@@ -163,8 +227,8 @@ pub fn load_bars(rbar: Arc<RBar>, app: &Application) -> Result<()> {
                 continue;
             }
         };
-        Bar::create(app, rbar.clone(), &monitor)?;
+        bars.push(Bar::create(app, rbar.clone(), &monitor)?);
     }
 
-    Ok(())
+    Ok(bars)
 }
@@ -15,15 +15,15 @@ pub struct Config {
 
 impl Config {
     /// Load a configuration from a file.
-    pub fn load() -> (Self, PathBuf) {
-        // todo: handle errors
-        let config_dir = Config::get_path();
+    pub fn load() -> crate::Result<Self> {
+        let path = Config::get_path();
 
-        let file = fs::File::open(&config_dir).expect("File to exist");
-        (
-            serde_json::from_reader(file).expect("Config to be created"),
-            config_dir,
-        )
+        let file = fs::File::open(&path)
+            .map_err(|e| format!("Failed to open '{}': {}", path.display(), e))?;
+        let config = serde_json::from_reader(file)
+            .map_err(|e| format!("Failed to parse '{}': {}", path.display(), e))?;
+
+        Ok(config)
     }
 
     pub fn get_dir() -> PathBuf {
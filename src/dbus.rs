@@ -0,0 +1,461 @@
+//! D-Bus interface of a running rbar instance.
+//!
+//! The object is exported next to the interfaces of [Application] on its
+//! session bus connection, so it lives at `/com/migueldamota/rbar` under the
+//! already owned `com.migueldamota.rbar` name.
+//!
+//! Module states are converted from JSON: objects are `a{sv}`, arrays `av`,
+//! integers `x` or `t` and other numbers `d`. D-Bus has no null, so null values
+//! are left out of objects and arrays, and are an empty `a{sv}` on their own.
+
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use gtk::{
+    gio::{DBusConnection, DBusMethodInvocation, DBusNodeInfo},
+    glib::{self, Variant, VariantDict},
+    prelude::*,
+    Application,
+};
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, warn};
+
+use crate::{
+    ipc::{Request, Response},
+    modules::ModuleEventKind,
+    rbar::{AppState, RBar, APP_ID},
+};
+
+const INTROSPECTION: &str = r#"
+<node>
+  <interface name="com.migueldamota.rbar">
+    <method name="ReloadConfig"/>
+    <method name="ReloadStyle"/>
+    <method name="ToggleBar">
+      <arg name="output" type="s" direction="in"/>
+    </method>
+    <!-- id, config id (empty if none), name, output, position and lifecycle state -->
+    <method name="ListModules">
+      <arg name="modules" type="a(tsssss)" direction="out"/>
+    </method>
+    <method name="ModuleState">
+      <arg name="id" type="s" direction="in"/>
+      <arg name="state" type="v" direction="out"/>
+    </method>
+    <method name="ModuleAction">
+      <arg name="id" type="s" direction="in"/>
+      <arg name="action" type="s" direction="in"/>
+    </method>
+    <method name="Trigger">
+      <arg name="name" type="s" direction="in"/>
+    </method>
+    <method name="Pause"/>
+    <method name="Resume"/>
+    <signal name="ModuleStateChanged">
+      <arg name="id" type="t"/>
+      <arg name="name" type="s"/>
+      <arg name="state" type="v"/>
+    </signal>
+    <signal name="ModuleEvent">
+      <arg name="id" type="t"/>
+      <arg name="name" type="s"/>
+      <arg name="event" type="s"/>
+    </signal>
+  </interface>
+</node>
+"#;
+
+/// Register the rbar object on the session bus connection of the application.
+pub fn register(app: &Application, state: Rc<RefCell<AppState>>) -> crate::Result<()> {
+    let (Some(connection), Some(path)) = (app.dbus_connection(), app.dbus_object_path()) else {
+        return Err("Application is not registered on the session bus".into());
+    };
+
+    export(&connection, &path, {
+        let state = state.clone();
+        move |request| state.borrow_mut().handle(request)
+    })?;
+
+    emit_module_events(connection, path.to_string(), state);
+
+    debug!("Registered D-Bus object at '{}'", path);
+
+    Ok(())
+}
+
+/// Export the rbar object at `path`, answering method calls with `handle`.
+fn export(
+    connection: &DBusConnection,
+    path: &str,
+    handle: impl Fn(Request) -> Response + 'static,
+) -> crate::Result<()> {
+    let node = DBusNodeInfo::for_xml(INTROSPECTION)?;
+    let interface = node
+        .lookup_interface(APP_ID)
+        .ok_or("Missing interface in introspection data")?;
+
+    connection.register_object(
+        path,
+        &interface,
+        move |_, _, _, _, method, parameters, invocation| {
+            handle_method_call(&handle, method, parameters, invocation);
+        },
+        |_, _, _, _, _| ().to_variant(),
+        |_, _, _, _, _, _| false,
+    )?;
+
+    Ok(())
+}
+
+fn handle_method_call(
+    handle: &impl Fn(Request) -> Response,
+    method: &str,
+    parameters: Variant,
+    invocation: DBusMethodInvocation,
+) {
+    let request = match method {
+        "ReloadConfig" => Some(Request::ReloadConfig),
+        "ReloadStyle" => Some(Request::ReloadStyle),
+        "ToggleBar" => parameters
+            .get::<(String,)>()
+            .map(|(output,)| Request::ToggleBar { output }),
+        "ListModules" => Some(Request::ListModules),
+        "ModuleState" => parameters
+            .get::<(String,)>()
+            .map(|(id,)| Request::ModuleState { id }),
+        "ModuleAction" => parameters
+            .get::<(String, String)>()
+            .map(|(id, action)| Request::ModuleAction { id, action }),
+        "Trigger" => parameters
+            .get::<(String,)>()
+            .map(|(name,)| Request::Trigger { name }),
+        "Pause" => Some(Request::Pause),
+        "Resume" => Some(Request::Resume),
+        _ => None,
+    };
+
+    let Some(request) = request else {
+        invocation.return_dbus_error(
+            "org.freedesktop.DBus.Error.InvalidArgs",
+            &format!("Invalid call to '{}'", method),
+        );
+        return;
+    };
+
+    // Converts the data of the response to the out arguments.
+    let output: Option<fn(Value) -> Result<Variant, serde_json::Error>> = match request {
+        Request::ListModules => Some(modules_to_variant),
+        Request::ModuleState { .. } => Some(|data| Ok((to_variant(&data),).to_variant())),
+        _ => None,
+    };
+
+    match (handle(request), output) {
+        (Response::Ok { data }, Some(output)) => match output(data) {
+            Ok(output) => invocation.return_value(Some(&output)),
+            Err(e) => invocation.return_dbus_error(
+                &format!("{}.Error.Failed", APP_ID),
+                &format!("Invalid response: {}", e),
+            ),
+        },
+        (Response::Ok { .. }, None) => invocation.return_value(None),
+        (Response::Error { message }, _) => {
+            invocation.return_dbus_error(&format!("{}.Error.Failed", APP_ID), &message)
+        }
+    }
+}
+
+/// A module as listed by [crate::modules::ModuleInfo].
+#[derive(Deserialize)]
+struct ModuleInfo {
+    id: u64,
+    #[serde(default)]
+    config_id: Option<String>,
+    name: String,
+    output: String,
+    position: String,
+    state: String,
+}
+
+/// Convert the modules listed by `ListModules` to its out arguments.
+fn modules_to_variant(data: Value) -> Result<Variant, serde_json::Error> {
+    let modules: Vec<ModuleInfo> = serde_json::from_value(data)?;
+    let modules: Vec<_> = modules
+        .into_iter()
+        .map(|module| {
+            (
+                module.id,
+                module.config_id.unwrap_or_default(),
+                module.name,
+                module.output,
+                module.position,
+                module.state,
+            )
+        })
+        .collect();
+
+    Ok((modules,).to_variant())
+}
+
+/// Convert JSON to a variant, as described in the [module docs](self).
+fn to_variant(value: &Value) -> Variant {
+    match value {
+        Value::Null => VariantDict::new(None).end(),
+        Value::Bool(value) => value.to_variant(),
+        Value::Number(number) => match (number.as_i64(), number.as_u64()) {
+            (Some(number), _) => number.to_variant(),
+            (None, Some(number)) => number.to_variant(),
+            _ => number.as_f64().unwrap_or_default().to_variant(),
+        },
+        Value::String(value) => value.to_variant(),
+        Value::Array(values) => values
+            .iter()
+            .filter(|value| !value.is_null())
+            .map(to_variant)
+            .collect::<Vec<_>>()
+            .to_variant(),
+        Value::Object(values) => {
+            let dict = VariantDict::new(None);
+            for (key, value) in values.iter().filter(|(_, value)| !value.is_null()) {
+                dict.insert_value(key, &to_variant(value));
+            }
+            dict.end()
+        }
+    }
+}
+
+/// Emit `ModuleStateChanged` whenever a module publishes a state different from its last one,
+/// and `ModuleEvent` for every triggered `ipc` action.
+fn emit_module_events(connection: DBusConnection, path: String, state: Rc<RefCell<AppState>>) {
+    let mut rx = RBar::events().subscribe();
+
+    glib::spawn_future_local(async move {
+        let mut states = HashMap::new();
+
+        loop {
+            let event = match rx.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            };
+
+            let id = event.id as u64;
+            let (signal, parameters) = match event.kind {
+                ModuleEventKind::Update { data } => {
+                    if states.get(&event.id) == Some(&data) {
+                        continue;
+                    }
+
+                    // Modules only get new ids when the config is reloaded, forget
+                    // the states of the ones that are gone.
+                    if !states.contains_key(&event.id) {
+                        if let Ok(state) = state.try_borrow() {
+                            let loaded: HashSet<_> = state.modules().map(|m| m.id).collect();
+                            states.retain(|id, _| loaded.contains(id));
+                        }
+                    }
+
+                    let parameters = (id, event.name, to_variant(&data)).to_variant();
+                    states.insert(event.id, data);
+                    ("ModuleStateChanged", parameters)
+                }
+                ModuleEventKind::Action { event: action } => {
+                    ("ModuleEvent", (id, event.name, action).to_variant())
+                }
+                // Only streamed over the socket.
+                _ => continue,
+            };
+
+            if let Err(e) = connection.emit_signal(None, &path, APP_ID, signal, Some(&parameters)) {
+                warn!("Failed to emit {}: {}", signal, e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::RefCell,
+        io::{BufRead, BufReader},
+        process::{Child, Command, Stdio},
+        rc::Rc,
+    };
+
+    use gtk::gio::{Cancellable, DBusCallFlags, DBusConnectionFlags};
+    use serde_json::json;
+
+    use super::*;
+
+    const PATH: &str = "/com/migueldamota/rbar";
+
+    /// A private session bus, stopped once dropped.
+    struct Bus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl Bus {
+        /// Start a bus, `None` if `dbus-daemon` is not available.
+        fn start() -> Option<Self> {
+            let mut daemon = Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .ok()?;
+
+            let stdout = daemon.stdout.take()?;
+            // Stops the daemon if it fails to print its address.
+            let mut bus = Self {
+                daemon,
+                address: String::new(),
+            };
+            BufReader::new(stdout).read_line(&mut bus.address).ok()?;
+
+            bus.address.truncate(bus.address.trim_end().len());
+            (!bus.address.is_empty()).then_some(bus)
+        }
+
+        fn connect(&self) -> DBusConnection {
+            DBusConnection::for_address_sync(
+                &self.address,
+                DBusConnectionFlags::AUTHENTICATION_CLIENT
+                    | DBusConnectionFlags::MESSAGE_BUS_CONNECTION,
+                None,
+                Cancellable::NONE,
+            )
+            .unwrap()
+        }
+    }
+
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    #[test]
+    fn method_calls() {
+        let Some(bus) = Bus::start() else {
+            eprintln!("Skipping, dbus-daemon is not available");
+            return;
+        };
+
+        let context = glib::MainContext::new();
+        context
+            .with_thread_default(|| {
+                let requests = Rc::new(RefCell::new(Vec::new()));
+                let handled = requests.clone();
+
+                let service = bus.connect();
+                export(&service, PATH, move |request| {
+                    let response = match &request {
+                        Request::ListModules => Response::data(json!([{
+                            "id": 1,
+                            "name": "clock",
+                            "output": "eDP-1",
+                            "position": "right",
+                            "state": "running",
+                        }])),
+                        Request::ModuleState { .. } => Response::data(json!({ "percentage": 42 })),
+                        Request::ModuleAction { id, .. } => {
+                            Response::error(format!("No module with id '{}'", id))
+                        }
+                        _ => Response::ok(),
+                    };
+                    handled.borrow_mut().push(format!("{:?}", request));
+                    response
+                })
+                .unwrap();
+
+                let name = service.unique_name().unwrap();
+                let client = bus.connect();
+                let call = |method: &str, parameters: Option<Variant>| {
+                    context.block_on(client.call_future(
+                        Some(&name),
+                        PATH,
+                        APP_ID,
+                        method,
+                        parameters.as_ref(),
+                        None,
+                        DBusCallFlags::NONE,
+                        5000,
+                    ))
+                };
+
+                let modules = call("ListModules", None).unwrap();
+                assert_eq!(
+                    modules.get::<(Vec<(u64, String, String, String, String, String)>,)>(),
+                    Some((vec![(
+                        1,
+                        String::new(),
+                        "clock".to_string(),
+                        "eDP-1".to_string(),
+                        "right".to_string(),
+                        "running".to_string(),
+                    )],))
+                );
+
+                let state = call("ModuleState", Some(("1",).to_variant())).unwrap();
+                let state = VariantDict::new(state.child_value(0).as_variant().as_ref());
+                assert_eq!(state.lookup::<i64>("percentage").unwrap(), Some(42));
+
+                let toggled = call("ToggleBar", Some(("eDP-1",).to_variant())).unwrap();
+                assert_eq!(toggled.n_children(), 0);
+
+                let error = call("ModuleAction", Some(("3", "refresh").to_variant())).unwrap_err();
+                assert!(error
+                    .message()
+                    .contains("com.migueldamota.rbar.Error.Failed"));
+                assert!(error.message().contains("No module with id '3'"));
+
+                // Calls not matching the introspection never reach the handler.
+                assert!(call("ToggleBar", None).is_err());
+                assert!(call("Unknown", None).is_err());
+
+                assert_eq!(
+                    *requests.borrow(),
+                    [
+                        "ListModules",
+                        r#"ModuleState { id: "1" }"#,
+                        r#"ToggleBar { output: "eDP-1" }"#,
+                        r#"ModuleAction { id: "3", action: "refresh" }"#,
+                    ]
+                );
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn json_to_variant() {
+        let state = to_variant(&json!({
+            "text": "on",
+            "level": -1,
+            "ratio": 0.5,
+            "icon": null,
+            "values": [1, null, 2],
+        }));
+        assert_eq!(state.type_().as_str(), "a{sv}");
+
+        let state = VariantDict::new(Some(&state));
+        assert_eq!(
+            state.lookup::<String>("text").unwrap(),
+            Some("on".to_string())
+        );
+        assert_eq!(state.lookup::<i64>("level").unwrap(), Some(-1));
+        assert_eq!(state.lookup::<f64>("ratio").unwrap(), Some(0.5));
+        assert!(!state.contains("icon"));
+
+        let values = state.lookup_value("values", None).unwrap();
+        assert_eq!(values.type_().as_str(), "av");
+        assert_eq!(values.n_children(), 2);
+
+        assert_eq!(to_variant(&Value::Null).type_().as_str(), "a{sv}");
+    }
+}
@@ -0,0 +1,261 @@
+//! Pause updates while the system sleeps or the screens are off.
+//!
+//! Sleep is reported by logind's `PrepareForSleep` signal and screen blanking by
+//! the `ActiveChanged` signal of `org.freedesktop.ScreenSaver`.
+//!
+//! The power mode of outputs (DPMS) is watched with the
+//! `wlr-output-power-management-unstable-v1` protocol of wlroots compositors
+//! like sway, and updates pause while all outputs are off. A control for an
+//! output is exclusive in wlroots, so other clients like `wlopm` can't set the
+//! power mode of outputs while rbar runs. Turning them off with the compositor,
+//! e.g. `swaymsg "output * power off"`, is not affected.
+
+use std::{cell::RefCell, collections::HashMap, rc::Rc, thread};
+
+use gtk::{
+    gio::{self, BusType, DBusConnection, DBusSignalFlags},
+    glib,
+    prelude::*,
+    Application,
+};
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+use wayland_client::{
+    globals::{registry_queue_init, GlobalListContents},
+    protocol::{wl_output::WlOutput, wl_registry::WlRegistry},
+    Connection, Dispatch, Proxy, QueueHandle, WEnum,
+};
+use wayland_protocols_wlr::output_power_management::v1::client::{
+    zwlr_output_power_manager_v1::ZwlrOutputPowerManagerV1,
+    zwlr_output_power_v1::{self, ZwlrOutputPowerV1},
+};
+
+use crate::rbar::AppState;
+
+/// Why updates are paused. Updates resume once there is no reason left.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PauseReason {
+    /// The system is about to sleep.
+    Sleep,
+    /// The screen saver is active.
+    ScreenSaver,
+    /// All outputs are powered off.
+    OutputsOff,
+    /// Paused with `rbar msg pause`.
+    Request,
+}
+
+/// Watch for sleep, screen blanking and powered off outputs and pause the bars accordingly.
+pub fn watch(app: &Application, state: Rc<RefCell<AppState>>) {
+    // The Wayland connection is blocking, so it gets a thread of its own.
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    thread::spawn(move || {
+        if let Err(e) = watch_outputs(tx) {
+            warn!("Failed to watch the power mode of outputs: {}", e);
+        }
+    });
+
+    glib::spawn_future_local({
+        let state = state.clone();
+        async move {
+            while let Some(off) = rx.recv().await {
+                debug!("All outputs off: {}", off);
+                state.borrow_mut().set_paused(PauseReason::OutputsOff, off);
+            }
+        }
+    });
+
+    if let Some(connection) = app.dbus_connection() {
+        subscribe(
+            &connection,
+            "org.freedesktop.ScreenSaver",
+            "ActiveChanged",
+            PauseReason::ScreenSaver,
+            state.clone(),
+        );
+    }
+
+    glib::spawn_future_local(async move {
+        match gio::bus_get_future(BusType::System).await {
+            Ok(connection) => subscribe(
+                &connection,
+                "org.freedesktop.login1.Manager",
+                "PrepareForSleep",
+                PauseReason::Sleep,
+                state,
+            ),
+            Err(e) => warn!("Failed to connect to the system bus: {}", e),
+        }
+    });
+}
+
+/// Subscribe to a signal with a single boolean argument telling whether to pause.
+fn subscribe(
+    connection: &DBusConnection,
+    interface: &str,
+    signal: &str,
+    reason: PauseReason,
+    state: Rc<RefCell<AppState>>,
+) {
+    connection.signal_subscribe(
+        None,
+        Some(interface),
+        Some(signal),
+        None,
+        None,
+        DBusSignalFlags::NONE,
+        move |_, _, _, _, signal, parameters| {
+            let Some((paused,)) = parameters.get::<(bool,)>() else {
+                warn!("Invalid parameters for {}: {}", signal, parameters);
+                return;
+            };
+
+            debug!("{}: {}", signal, paused);
+            state.borrow_mut().set_paused(reason, paused);
+        },
+    );
+}
+
+/// Watch the power mode of all outputs, sending whether all of them are off
+/// whenever that changes. Runs until the connection fails.
+fn watch_outputs(tx: mpsc::UnboundedSender<bool>) -> crate::Result<()> {
+    let connection = Connection::connect_to_env()?;
+    let (globals, mut queue) = registry_queue_init::<Outputs>(&connection)?;
+    let handle = queue.handle();
+
+    let manager = globals
+        .bind(&handle, 1..=1, ())
+        .map_err(|e| format!("No output power management: {}", e))?;
+
+    let mut outputs = Outputs {
+        manager,
+        outputs: HashMap::new(),
+        off: false,
+        tx,
+    };
+
+    let names: Vec<u32> = globals.contents().with_list(|list| {
+        list.iter()
+            .filter(|global| global.interface == WlOutput::interface().name)
+            .map(|global| global.name)
+            .collect()
+    });
+    for name in names {
+        outputs.add(globals.registry(), name, &handle);
+    }
+
+    loop {
+        queue.blocking_dispatch(&mut outputs)?;
+    }
+}
+
+/// [Outputs] tracks the power mode of all outputs.
+struct Outputs {
+    manager: ZwlrOutputPowerManagerV1,
+    /// Outputs by the name of their global, and whether they are on.
+    outputs: HashMap<u32, (WlOutput, ZwlrOutputPowerV1, bool)>,
+    /// Whether all outputs were off when last sent.
+    off: bool,
+    tx: mpsc::UnboundedSender<bool>,
+}
+
+impl Outputs {
+    /// Watch the output of the global. Its power mode is sent right away.
+    fn add(&mut self, registry: &WlRegistry, name: u32, handle: &QueueHandle<Self>) {
+        let output: WlOutput = registry.bind(name, 1, handle, ());
+        let power = self.manager.get_output_power(&output, handle, name);
+        self.outputs.insert(name, (output, power, true));
+    }
+
+    /// Stop watching the output of the global.
+    fn remove(&mut self, name: u32) {
+        if let Some((output, power, _)) = self.outputs.remove(&name) {
+            power.destroy();
+            if output.version() >= 3 {
+                output.release();
+            }
+        }
+        self.update();
+    }
+
+    /// Send whether all outputs are off, if that changed.
+    fn update(&mut self) {
+        let off = !self.outputs.is_empty() && self.outputs.values().all(|(_, _, on)| !on);
+        if off != self.off {
+            self.off = off;
+            // Nobody is listening anymore once the application quit.
+            let _ = self.tx.send(off);
+        }
+    }
+}
+
+impl Dispatch<WlRegistry, GlobalListContents> for Outputs {
+    fn event(
+        outputs: &mut Self,
+        registry: &WlRegistry,
+        event: <WlRegistry as Proxy>::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        handle: &QueueHandle<Self>,
+    ) {
+        use wayland_client::protocol::wl_registry::Event;
+
+        match event {
+            Event::Global {
+                name, interface, ..
+            } if interface == WlOutput::interface().name => outputs.add(registry, name, handle),
+            Event::GlobalRemove { name } => outputs.remove(name),
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ZwlrOutputPowerV1, u32> for Outputs {
+    fn event(
+        outputs: &mut Self,
+        _: &ZwlrOutputPowerV1,
+        event: zwlr_output_power_v1::Event,
+        name: &u32,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_output_power_v1::Event::Mode { mode } => {
+                if let Some((_, _, on)) = outputs.outputs.get_mut(name) {
+                    *on = mode != WEnum::Value(zwlr_output_power_v1::Mode::Off);
+                }
+                outputs.update();
+            }
+            // E.g. the output doesn't support power management or another client controls it.
+            zwlr_output_power_v1::Event::Failed => {
+                debug!("Can't watch the power mode of output {}", name);
+                outputs.remove(*name);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<WlOutput, ()> for Outputs {
+    fn event(
+        _: &mut Self,
+        _: &WlOutput,
+        _: <WlOutput as Proxy>::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ZwlrOutputPowerManagerV1, ()> for Outputs {
+    fn event(
+        _: &mut Self,
+        _: &ZwlrOutputPowerManagerV1,
+        _: <ZwlrOutputPowerManagerV1 as Proxy>::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
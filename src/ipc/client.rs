@@ -0,0 +1,191 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+};
+
+use super::{socket_path, Request, Response};
+
+const USAGE: &str = "\
+Usage: rbar msg <command>
+
+Commands:
+    reload-config
+    reload-style
+    toggle-bar <output>
+    list-modules
+    module <id> state
+    module <id> action <name>
+    trigger <name>
+    pause
+    resume
+    subscribe";
+
+/// Run `rbar msg` with the given arguments and return the exit code.
+pub fn run(args: &[String]) -> i32 {
+    let request = match parse(args) {
+        Ok(request) => request,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            return 2;
+        }
+    };
+
+    if let Request::Subscribe = request {
+        return match subscribe() {
+            Ok(()) => 0,
+            Err(e) => {
+                eprintln!("Failed to talk to rbar: {}", e);
+                1
+            }
+        };
+    }
+
+    match send(&request) {
+        Ok(Response::Ok { data }) => {
+            if !data.is_null() {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&data).unwrap_or_default()
+                );
+            }
+            0
+        }
+        Ok(Response::Error { message }) => {
+            eprintln!("{}", message);
+            1
+        }
+        Err(e) => {
+            eprintln!("Failed to talk to rbar: {}", e);
+            1
+        }
+    }
+}
+
+/// Send a single request to the running rbar and wait for its response.
+pub fn send(request: &Request) -> crate::Result<Response> {
+    let mut stream = UnixStream::connect(socket_path())?;
+
+    let mut line = serde_json::to_string(request)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+
+    let mut response = String::new();
+    BufReader::new(stream).read_line(&mut response)?;
+
+    Ok(serde_json::from_str(&response)?)
+}
+
+/// Subscribe to module updates and print them, one JSON object per line.
+fn subscribe() -> crate::Result<()> {
+    let mut stream = UnixStream::connect(socket_path())?;
+
+    let mut line = serde_json::to_string(&Request::Subscribe)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+
+    let mut lines = BufReader::new(stream).lines();
+
+    let response = lines.next().ok_or("Connection closed")??;
+    if let Response::Error { message } = serde_json::from_str(&response)? {
+        return Err(message.into());
+    }
+
+    for line in lines {
+        println!("{}", line?);
+    }
+
+    Ok(())
+}
+
+fn parse(args: &[String]) -> Result<Request, String> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    let request = match args.as_slice() {
+        ["reload-config"] => Request::ReloadConfig,
+        ["reload-style"] => Request::ReloadStyle,
+        ["toggle-bar", output] => Request::ToggleBar {
+            output: output.to_string(),
+        },
+        ["list-modules"] => Request::ListModules,
+        ["module", id, "state"] => Request::ModuleState { id: id.to_string() },
+        ["module", id, "action", action] => Request::ModuleAction {
+            id: id.to_string(),
+            action: action.to_string(),
+        },
+        ["trigger", name] => Request::Trigger {
+            name: name.to_string(),
+        },
+        ["pause"] => Request::Pause,
+        ["resume"] => Request::Resume,
+        ["subscribe"] => Request::Subscribe,
+        [] => return Err("Missing command".to_string()),
+        _ => return Err(format!("Invalid command '{}'", args.join(" "))),
+    };
+
+    Ok(request)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Request, String> {
+        let args: Vec<String> = args.split_whitespace().map(String::from).collect();
+        super::parse(&args)
+    }
+
+    #[test]
+    fn commands() {
+        assert!(matches!(parse("reload-config"), Ok(Request::ReloadConfig)));
+        assert!(matches!(parse("reload-style"), Ok(Request::ReloadStyle)));
+        assert!(matches!(parse("list-modules"), Ok(Request::ListModules)));
+        assert!(matches!(parse("pause"), Ok(Request::Pause)));
+        assert!(matches!(parse("resume"), Ok(Request::Resume)));
+        assert!(matches!(parse("subscribe"), Ok(Request::Subscribe)));
+    }
+
+    #[test]
+    fn arguments() {
+        assert!(matches!(
+            parse("toggle-bar eDP-1"),
+            Ok(Request::ToggleBar { output }) if output == "eDP-1"
+        ));
+        assert!(matches!(
+            parse("module clock state"),
+            Ok(Request::ModuleState { id }) if id == "clock"
+        ));
+        assert!(matches!(
+            parse("module 3 action refresh"),
+            Ok(Request::ModuleAction { id, action }) if id == "3" && action == "refresh"
+        ));
+        assert!(matches!(
+            parse("trigger vpn"),
+            Ok(Request::Trigger { name }) if name == "vpn"
+        ));
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(parse("").unwrap_err(), "Missing command");
+        assert_eq!(
+            parse("toggle-bar").unwrap_err(),
+            "Invalid command 'toggle-bar'"
+        );
+        assert_eq!(
+            parse("module 3 action").unwrap_err(),
+            "Invalid command 'module 3 action'"
+        );
+        assert!(parse("reload-config now").is_err());
+        assert!(parse("unknown").is_err());
+    }
+
+    #[test]
+    fn wire_format() {
+        let request = parse("module clock action refresh").unwrap();
+
+        assert_eq!(
+            serde_json::to_string(&request).unwrap(),
+            r#"{"command":"module_action","id":"clock","action":"refresh"}"#
+        );
+    }
+}
@@ -0,0 +1,97 @@
+//! Control socket of a running rbar instance.
+//!
+//! The protocol is JSON lines: every line sent to the socket is a [Request]
+//! and is answered with exactly one line containing a [Response].
+//!
+//! After a [Request::Subscribe] the connection only carries
+//! [ModuleEvent](crate::modules::ModuleEvent)s, one per line, until it is closed.
+
+use std::{env, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+pub mod client;
+pub mod server;
+
+/// A request sent to the running rbar.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Request {
+    /// Reload `config.json` and rebuild all bars.
+    ReloadConfig,
+    /// Reload `style.css`.
+    ReloadStyle,
+    /// Show or hide the bar on the given output, e.g. `eDP-1`.
+    ToggleBar { output: String },
+    /// List all loaded modules.
+    ListModules,
+    /// Get the latest state of a module.
+    ModuleState { id: String },
+    /// Run a module-defined action.
+    ModuleAction { id: String, action: String },
+    /// Refresh all modules waiting for the named trigger.
+    Trigger { name: String },
+    /// Pause all updates, e.g. while the screens are off.
+    Pause,
+    /// Resume updates paused with [Request::Pause].
+    Resume,
+    /// Stream every module update on this connection.
+    Subscribe,
+}
+
+/// A response to a [Request].
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Response {
+    Ok {
+        #[serde(default, skip_serializing_if = "Value::is_null")]
+        data: Value,
+    },
+    Error {
+        message: String,
+    },
+}
+
+impl Response {
+    /// Successful response without any data.
+    pub fn ok() -> Self {
+        Self::Ok { data: Value::Null }
+    }
+
+    /// Successful response carrying serialized data.
+    pub fn data<T: Serialize>(data: T) -> Self {
+        match serde_json::to_value(data) {
+            Ok(data) => Self::Ok { data },
+            Err(e) => Self::error(format!("Failed to serialize response: {}", e)),
+        }
+    }
+
+    /// Failed response.
+    pub fn error(message: impl Into<String>) -> Self {
+        Self::Error {
+            message: message.into(),
+        }
+    }
+}
+
+impl From<crate::Result<()>> for Response {
+    fn from(result: crate::Result<()>) -> Self {
+        match result {
+            Ok(()) => Self::ok(),
+            Err(e) => Self::error(e.to_string()),
+        }
+    }
+}
+
+/// Get path to the control socket.
+///
+/// Default: `$XDG_RUNTIME_DIR/rbar.sock`, or `/tmp/rbar-<uid>.sock` without a
+/// runtime directory, so users don't share a socket.
+pub fn socket_path() -> PathBuf {
+    match dirs::runtime_dir() {
+        Some(dir) => dir.join("rbar.sock"),
+        // Safety: getuid can't fail.
+        None => env::temp_dir().join(format!("rbar-{}.sock", unsafe { libc::getuid() })),
+    }
+}
@@ -0,0 +1,146 @@
+use std::{
+    fs,
+    os::unix::net::{UnixListener as StdUnixListener, UnixStream as StdUnixStream},
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{unix::OwnedWriteHalf, UnixListener, UnixStream},
+    sync::{broadcast::error::RecvError, mpsc, oneshot},
+};
+use tracing::{debug, error, warn};
+
+use crate::rbar::RBar;
+
+use super::{socket_path, Request, Response};
+
+/// A request together with the channel its response is sent back on.
+pub type Message = (Request, oneshot::Sender<Response>);
+
+/// [Socket] is the control socket, removed once dropped.
+pub struct Socket {
+    path: PathBuf,
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        debug!("Removing '{}'", self.path.display());
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("Failed to remove '{}': {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Start listening on the control socket. Keep the [Socket] until rbar exits.
+///
+/// Requests are forwarded to `tx` and handled on the GTK main thread.
+pub fn start(tx: mpsc::Sender<Message>) -> Option<Socket> {
+    let path = socket_path();
+
+    let listener = match bind(&path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Failed to listen on '{}': {}", path.display(), e);
+            return None;
+        }
+    };
+    debug!("Listening on '{}'", path.display());
+
+    RBar::runtime().spawn(async move {
+        if let Err(e) = listen(listener, tx).await {
+            error!("Failed to accept ipc connections: {}", e);
+        }
+    });
+
+    Some(Socket { path })
+}
+
+fn bind(path: &Path) -> std::io::Result<StdUnixListener> {
+    if path.exists() {
+        // Only remove the socket if no other instance is listening on it.
+        if StdUnixStream::connect(path).is_ok() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AddrInUse,
+                "another rbar instance is running",
+            ));
+        }
+        fs::remove_file(path)?;
+    }
+
+    let listener = StdUnixListener::bind(path)?;
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
+
+async fn listen(listener: StdUnixListener, tx: mpsc::Sender<Message>) -> std::io::Result<()> {
+    let listener = UnixListener::from_std(listener)?;
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, tx).await {
+                warn!("Failed to handle ipc connection: {}", e);
+            }
+        });
+    }
+}
+
+async fn handle_connection(stream: UnixStream, tx: mpsc::Sender<Message>) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(Request::Subscribe) => return stream_events(writer).await,
+            Ok(request) => dispatch(request, &tx).await,
+            Err(e) => Response::error(format!("Invalid request: {}", e)),
+        };
+
+        write_line(&mut writer, &response).await?;
+    }
+
+    Ok(())
+}
+
+/// Write every module update to the connection until the client goes away.
+async fn stream_events(mut writer: OwnedWriteHalf) -> std::io::Result<()> {
+    let mut rx = RBar::events().subscribe();
+
+    write_line(&mut writer, &Response::ok()).await?;
+
+    loop {
+        match rx.recv().await {
+            Ok(event) => write_line(&mut writer, &event).await?,
+            Err(RecvError::Lagged(skipped)) => {
+                warn!("Event subscriber lagged behind, skipped {} events", skipped)
+            }
+            Err(RecvError::Closed) => return Ok(()),
+        }
+    }
+}
+
+async fn write_line<T: Serialize>(writer: &mut OwnedWriteHalf, value: &T) -> std::io::Result<()> {
+    let mut line = serde_json::to_string(value)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await
+}
+
+async fn dispatch(request: Request, tx: &mpsc::Sender<Message>) -> Response {
+    let (response_tx, response_rx) = oneshot::channel();
+
+    if tx.send((request, response_tx)).await.is_err() {
+        return Response::error("rbar is shutting down");
+    }
+
+    response_rx
+        .await
+        .unwrap_or_else(|_| Response::error("Request was dropped"))
+}
@@ -3,14 +3,29 @@ use tracing::{debug, error};
 
 mod bar;
 mod config;
+mod dbus;
 mod error;
+mod idle;
+mod ipc;
 mod modules;
+mod plugins;
 mod rbar;
+mod scheduler;
+mod sources;
 mod style;
+mod wasm;
 
 type Result<T, E = Box<dyn std::error::Error>> = std::result::Result<T, E>;
 
 fn main() {
+    ignore_realtime_signals();
+
+    // `rbar msg <command>` talks to the running instance.
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().is_some_and(|arg| arg == "msg") {
+        std::process::exit(ipc::client::run(&args[1..]));
+    }
+
     std::env::set_var("GSK_RENDERER", "cairo");
 
     use tracing::Level;
@@ -23,10 +38,35 @@ fn main() {
         std::env::var("GSK_RENDERER").unwrap()
     );
 
-    let app = RBar::new();
+    let app = match RBar::new() {
+        Ok(app) => app,
+        Err(e) => {
+            error!("Failed to load config: {}", e);
+            std::process::exit(1);
+        }
+    };
 
     if let Err(e) = app.start() {
         error!("There was an error while running app: {}", e);
         std::process::exit(1);
     }
 }
+
+/// Ignore `SIGRTMIN+<n>` until a custom module listens for it.
+///
+/// By default these signals terminate the process. `pkill -RTMIN+8 rbar` would
+/// kill rbar before its modules started, and any running `rbar msg` as well.
+/// A handler is used instead of `SIG_IGN`, which commands run by rbar would inherit.
+fn ignore_realtime_signals() {
+    extern "C" fn ignore(_: libc::c_int) {}
+
+    for signum in libc::SIGRTMIN()..=libc::SIGRTMAX() {
+        // Safety: the handler does nothing, so it is async-signal-safe.
+        unsafe {
+            libc::signal(
+                signum,
+                ignore as extern "C" fn(libc::c_int) as libc::sighandler_t,
+            )
+        };
+    }
+}
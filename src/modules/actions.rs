@@ -0,0 +1,225 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc, time::Duration};
+
+use gtk::{
+    glib::{self, SourceId},
+    prelude::*,
+    EventControllerMotion, EventControllerScroll, EventControllerScrollFlags, GestureClick,
+    PropagationPhase, Widget,
+};
+use serde::Deserialize;
+use tokio::process::Command;
+use tracing::{debug, error, warn};
+
+use crate::rbar::RBar;
+
+use super::{ActionFn, ModuleEvent, ModuleEventKind};
+
+/// User input a module can react to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Gesture {
+    /// Delayed by the double click time if there is a [Gesture::DoubleClick] action.
+    LeftClick,
+    MiddleClick,
+    RightClick,
+    /// Replaces the [Gesture::LeftClick] of its first click.
+    DoubleClick,
+    ScrollUp,
+    ScrollDown,
+    Hover,
+}
+
+/// What to do when a [Gesture] happens on a module.
+///
+/// Example: `{ "right_click": { "exec": "pavucontrol" } }`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// Run a shell command with `sh -c`.
+    Exec(String),
+    /// Publish a named event to IPC subscribers.
+    Ipc(String),
+    /// Run a module-defined action, same as `rbar msg module <id> action <name>`.
+    Builtin(String),
+}
+
+/// Attach gesture controllers for all configured actions to the widget.
+pub fn setup(
+    widget: &impl IsA<Widget>,
+    actions: &HashMap<Gesture, Action>,
+    id: usize,
+    name: &'static str,
+    builtin: ActionFn,
+) {
+    let has_action = |gestures: &[Gesture]| gestures.iter().any(|g| actions.contains_key(g));
+
+    let click_gestures = [
+        Gesture::LeftClick,
+        Gesture::MiddleClick,
+        Gesture::RightClick,
+        Gesture::DoubleClick,
+    ];
+    let has_click = has_action(&click_gestures);
+    let has_scroll = has_action(&[Gesture::ScrollUp, Gesture::ScrollDown]);
+    let has_hover = has_action(&[Gesture::Hover]);
+
+    let actions = actions.clone();
+    let on_gesture = Rc::new(move |gesture: Gesture| {
+        if let Some(action) = actions.get(&gesture) {
+            debug!(
+                "Running {:?} for {:?} on module '{}'",
+                action, gesture, name
+            );
+            if let Err(e) = run(action, id, name, &builtin) {
+                error!("Failed to run action {:?}: {}", action, e);
+            }
+        }
+    });
+
+    if has_click {
+        let click = GestureClick::new();
+        // Run before widgets like buttons handle the click themselves.
+        click.set_propagation_phase(PropagationPhase::Capture);
+        click.set_button(0);
+
+        // With a double click action, a left click only counts once no second
+        // click followed within the double click time.
+        let delay_left_click = has_action(&[Gesture::DoubleClick]);
+        let pending: Rc<RefCell<Option<SourceId>>> = Rc::default();
+
+        let on_gesture = on_gesture.clone();
+        click.connect_pressed(move |gesture, n_press, _, _| {
+            match press(gesture.current_button(), n_press, delay_left_click) {
+                Press::DelayLeftClick => {
+                    let on_gesture = on_gesture.clone();
+                    let fired = pending.clone();
+                    let source = glib::timeout_add_local_once(double_click_time(), move || {
+                        fired.take();
+                        on_gesture(Gesture::LeftClick);
+                    });
+                    if let Some(previous) = pending.replace(Some(source)) {
+                        previous.remove();
+                    }
+                }
+                Press::Run(gesture) => {
+                    if gesture == Gesture::DoubleClick {
+                        if let Some(source) = pending.take() {
+                            source.remove();
+                        }
+                    }
+                    on_gesture(gesture);
+                }
+                Press::Ignore => {}
+            }
+        });
+        widget.add_controller(click);
+    }
+
+    if has_scroll {
+        let scroll = EventControllerScroll::new(
+            EventControllerScrollFlags::VERTICAL | EventControllerScrollFlags::DISCRETE,
+        );
+
+        let on_gesture = on_gesture.clone();
+        scroll.connect_scroll(move |_, _, dy| {
+            on_gesture(if dy < 0.0 {
+                Gesture::ScrollUp
+            } else {
+                Gesture::ScrollDown
+            });
+            gtk::glib::Propagation::Stop
+        });
+        widget.add_controller(scroll);
+    }
+
+    if has_hover {
+        let motion = EventControllerMotion::new();
+        motion.connect_enter(move |_, _, _| on_gesture(Gesture::Hover));
+        widget.add_controller(motion);
+    }
+}
+
+/// What to do for a press of a mouse button.
+#[derive(Debug, PartialEq, Eq)]
+enum Press {
+    Run(Gesture),
+    /// Run a left click once no second click followed within the double click time.
+    DelayLeftClick,
+    Ignore,
+}
+
+/// Map the `n_press`th press of a button to its gesture.
+///
+/// Only with a double click action, left clicks are delayed and two presses
+/// are a double click. Otherwise every press is a left click.
+fn press(button: u32, n_press: i32, delay_left_click: bool) -> Press {
+    match (button, n_press) {
+        (1, 1) if delay_left_click => Press::DelayLeftClick,
+        (1, 2) if delay_left_click => Press::Run(Gesture::DoubleClick),
+        (1, _) if delay_left_click => Press::Ignore,
+        (1, _) => Press::Run(Gesture::LeftClick),
+        (2, _) => Press::Run(Gesture::MiddleClick),
+        (3, _) => Press::Run(Gesture::RightClick),
+        _ => Press::Ignore,
+    }
+}
+
+/// Time within which two clicks are a double click, as configured in GTK.
+fn double_click_time() -> Duration {
+    let millis = gtk::Settings::default().map_or(400, |settings| settings.gtk_double_click_time());
+    Duration::from_millis(millis.max(0) as u64)
+}
+
+fn run(action: &Action, id: usize, name: &'static str, builtin: &ActionFn) -> crate::Result<()> {
+    match action {
+        Action::Exec(command) => {
+            let command = command.clone();
+            RBar::runtime().spawn(async move {
+                match Command::new("sh").arg("-c").arg(&command).status().await {
+                    Ok(status) if !status.success() => {
+                        warn!("'{}' exited with {}", command, status)
+                    }
+                    Ok(_) => {}
+                    Err(e) => error!("Failed to run '{}': {}", command, e),
+                }
+            });
+        }
+        Action::Ipc(event) => {
+            // Nobody might be listening.
+            let _ = RBar::events().send(ModuleEvent {
+                id,
+                name,
+                kind: ModuleEventKind::Action {
+                    event: event.clone(),
+                },
+            });
+        }
+        Action::Builtin(action) => builtin(action)?,
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presses_with_double_click() {
+        assert_eq!(press(1, 1, true), Press::DelayLeftClick);
+        assert_eq!(press(1, 2, true), Press::Run(Gesture::DoubleClick));
+        assert_eq!(press(1, 3, true), Press::Ignore);
+        assert_eq!(press(3, 2, true), Press::Run(Gesture::RightClick));
+    }
+
+    #[test]
+    fn presses_without_double_click() {
+        // Every click counts, even in quick succession.
+        for n_press in 1..=3 {
+            assert_eq!(press(1, n_press, false), Press::Run(Gesture::LeftClick));
+        }
+        assert_eq!(press(2, 1, false), Press::Run(Gesture::MiddleClick));
+        assert_eq!(press(3, 1, false), Press::Run(Gesture::RightClick));
+        assert_eq!(press(8, 1, false), Press::Ignore);
+    }
+}
@@ -1,24 +1,28 @@
-use chrono::Local;
-use gtk::{glib, prelude::*, Button, Label};
-use serde::Deserialize;
-use tokio::time::{sleep, Duration};
+use chrono::{Datelike, Local};
+use gtk::{glib, prelude::*, Button, Calendar, Label, Widget};
+use serde::{Deserialize, Deserializer};
+use tokio::{sync::mpsc, time::Duration};
 use tracing::error;
 
 use crate::rbar::RBar;
 
-use super::{BaseModuleConfig, Events, Module, WidgetContext};
+use super::{
+    format::{Context, Template},
+    BaseModuleConfig, Events, Module, WidgetContext,
+};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Clock {
     config: BaseModuleConfig,
 
     /// Clock format
-    /// Default: `%a %d/%m/%Y - %H:%M:%S %p`
+    /// Default: `{time:%a %d/%m/%Y - %H:%M:%S %p}`
     /// Example: Wed 01/01/2022 - 00:00:00 AM
     ///
-    /// Formatting is based on [chrono::format::strftime](https://docs.rs/chrono/0.4/chrono/format/strftime/index.html).
-    #[serde(default = "default_format")]
-    pub format: String,
+    /// `{time}` is formatted based on [chrono::format::strftime](https://docs.rs/chrono/0.4/chrono/format/strftime/index.html).
+    /// A format without placeholders is used as the strftime format of `{time}`, braces included.
+    #[serde(default = "default_format", deserialize_with = "deserialize_format")]
+    pub format: Template,
 }
 
 impl Module<Button> for Clock {
@@ -29,54 +33,208 @@ impl Module<Button> for Clock {
         "clock"
     }
 
-    fn controllers(&self, context: &WidgetContext<Self::Send>) -> crate::Result<()> {
+    fn controllers(
+        &self,
+        context: &WidgetContext<Self::Send, Self::Receive>,
+        mut rx: mpsc::Receiver<Self::Receive>,
+    ) -> crate::Result<()> {
         let tx = context.tx.clone();
-        let duration = Duration::from_millis(500);
 
-        RBar::runtime().spawn(async move {
+        // Tick when the shown time changes, shared by all clocks.
+        let period = if self.shows_seconds() { 1 } else { 60 };
+        let mut source = RBar::scheduler().every(Duration::from_secs(period));
+
+        let lifecycle = context.lifecycle.clone();
+        context.lifecycle.spawn(async move {
             loop {
+                // Update early when the widget asks for a refresh.
+                tokio::select! {
+                    Some(()) = source.next() => {}
+                    Some(()) = rx.recv() => {}
+                    else => break,
+                }
+
                 if let Err(e) = tx.send(Events::Update(())).await {
                     error!("Error while sending date: {}", e);
                     break;
                 }
-
-                sleep(duration).await;
+                lifecycle.running().await;
             }
         });
 
         Ok(())
     }
 
-    fn widget(&self, context: WidgetContext<Self::Send>) -> crate::Result<Button> {
-        let date = Local::now();
-
+    fn widget(&self, context: WidgetContext<Self::Send, Self::Receive>) -> crate::Result<Button> {
         let button = Button::new();
-        let label = Label::builder()
-            .label(date.format(&self.format).to_string())
-            .build();
+        let label = Label::new(None);
+        label.set_markup(&self.format.render(&self.format_context(&())));
 
         button.set_child(Some(&label));
         button.show();
 
-        let format = self.format.clone();
+        let module = self.clone();
 
         let mut rx = context.subscribe();
         glib::spawn_future_local(async move {
-            while rx.recv().await.is_ok() {
-                let date = Local::now();
-                let formatted_date = date.format(&format).to_string();
-                label.set_label(&formatted_date);
+            while let Some(data) = rx.recv().await {
+                label.set_markup(&module.format.render(&module.format_context(&data)));
             }
         });
 
         Ok(button)
     }
 
+    fn popover(
+        &self,
+        context: &WidgetContext<Self::Send, Self::Receive>,
+    ) -> crate::Result<Option<Widget>> {
+        let calendar = Calendar::new();
+
+        // Keep today selected when the date changes.
+        let mut rx = context.subscribe();
+        let widget = calendar.clone();
+        glib::spawn_future_local(async move {
+            let mut day = None;
+
+            while rx.recv().await.is_some() {
+                let today = Local::now().ordinal();
+                if day == Some(today) {
+                    continue;
+                }
+
+                if let Ok(now) = glib::DateTime::now_local() {
+                    calendar.select_day(&now);
+                }
+                day = Some(today);
+            }
+        });
+
+        Ok(Some(widget.upcast()))
+    }
+
+    fn format_context(&self, _data: &Self::Send) -> Context {
+        Context::new().with("time", Local::now())
+    }
+
+    /// The full date, e.g. `Wednesday, 01 January 2022`.
+    fn tooltip(&self, _data: &Self::Send) -> Option<String> {
+        Some(Local::now().format("%A, %d %B %Y").to_string())
+    }
+
+    fn action(
+        &self,
+        name: &str,
+        context: &WidgetContext<Self::Send, Self::Receive>,
+    ) -> crate::Result<()> {
+        match name {
+            "refresh" => Ok(context.controller_tx.try_send(())?),
+            _ => Err(format!("Module '{}' has no action '{}'", Self::name(), name).into()),
+        }
+    }
+
     fn get_base_config(&self) -> &BaseModuleConfig {
         &self.config
     }
 }
 
-fn default_format() -> String {
-    "%a %d/%m/%Y - %H:%M:%S %p".to_string()
+impl Clock {
+    /// Check if the format or the tooltip show seconds, or anything changing
+    /// more often than every minute.
+    fn shows_seconds(&self) -> bool {
+        let templates = [Some(&self.format), self.config.tooltip.as_ref()];
+
+        templates
+            .into_iter()
+            .flatten()
+            .flat_map(|template| template.specs("time"))
+            // Without a spec the time is shown as `%H:%M`.
+            .any(|spec| spec.is_some_and(shows_seconds))
+    }
+}
+
+/// Check if a strftime format shows seconds.
+fn shows_seconds(spec: &str) -> bool {
+    let mut chars = spec.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            continue;
+        }
+
+        // Skip padding, width and precision, e.g. `%-S` or `%.3f`.
+        let specifier = chars.find(|c| !matches!(c, '-' | '_' | '0'..='9' | '.' | ':'));
+        if matches!(
+            specifier,
+            Some('S' | 'T' | 'X' | 'r' | 'c' | '+' | 's' | 'f')
+        ) {
+            return true;
+        }
+    }
+
+    false
+}
+
+fn default_format() -> Template {
+    "{time:%a %d/%m/%Y - %H:%M:%S %p}"
+        .parse()
+        .expect("default format to be valid")
+}
+
+fn deserialize_format<'de, D>(deserializer: D) -> Result<Template, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let format = String::deserialize(deserializer)?;
+
+    // Formats without placeholders are strftime formats, which may contain lone braces.
+    if !format.contains('{') {
+        return Ok(Template::placeholder("time", Some(&format)));
+    }
+
+    format.parse().map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn specs_with_seconds() {
+        for spec in ["%S", "%H:%M:%S", "%-S", "%T", "%.3f", "%s", "%c"] {
+            assert!(shows_seconds(spec), "{}", spec);
+        }
+        for spec in ["%H:%M", "%a %d/%m/%Y", "%%S", "S"] {
+            assert!(!shows_seconds(spec), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn tooltip_with_seconds() {
+        let clock = |config| -> Clock {
+            serde_json::from_value(json!({ "format": "%H:%M", "config": config })).unwrap()
+        };
+
+        assert!(!clock(json!({})).shows_seconds());
+        assert!(!clock(json!({ "tooltip": "{time:%A}" })).shows_seconds());
+        assert!(clock(json!({ "tooltip": "{time:%H:%M:%S}" })).shows_seconds());
+    }
+
+    #[test]
+    fn strftime_formats() {
+        let clock = |format: &str| -> Clock {
+            serde_json::from_value(json!({ "format": format, "config": {} })).unwrap()
+        };
+        let render = |format: &str| {
+            clock(format)
+                .format
+                .render(&Context::new().with("time", Local::now()))
+        };
+
+        assert_eq!(render("%Y}"), format!("{}}}", Local::now().format("%Y")));
+        assert_eq!(render("%Y}}"), format!("{}}}}}", Local::now().format("%Y")));
+        assert_eq!(render("{time:%Y}"), Local::now().format("%Y").to_string());
+        assert!(clock("%S}").shows_seconds());
+    }
 }
@@ -0,0 +1,409 @@
+//! Conditions used by `visible_when`.
+//!
+//! A condition is a small expression evaluated against a JSON context, e.g.
+//! `state == "discharging" && soc < 20` or `output == "eDP-1"`.
+//!
+//! Supported are string, number, boolean and `null` literals, dotted paths
+//! into the context (`battery.present`), comparisons (`==`, `!=`, `<`, `<=`,
+//! `>`, `>=`), `!`, `&&`, `||` and parentheses. A path on its own is true if
+//! its value is neither `null`, `false`, `0` nor empty.
+
+use std::{cmp::Ordering, fmt};
+
+use serde::Deserialize;
+use serde_json::Value;
+
+#[derive(Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Condition {
+    source: String,
+    expr: Expr,
+}
+
+impl Condition {
+    /// Evaluate the condition against the given context.
+    pub fn evaluate(&self, context: &Value) -> bool {
+        truthy(&self.expr.evaluate(context))
+    }
+
+    /// Check if the condition reads the value at `name` of the context, or below it.
+    pub fn uses(&self, name: &str) -> bool {
+        self.expr.uses(name)
+    }
+}
+
+impl TryFrom<String> for Condition {
+    type Error = String;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        let tokens = tokenize(&source)?;
+        let mut parser = Parser { tokens, pos: 0 };
+
+        let expr = parser.or()?;
+        if let Some(token) = parser.peek() {
+            return Err(format!("Unexpected {:?} in '{}'", token, source));
+        }
+
+        Ok(Self { source, expr })
+    }
+}
+
+impl fmt::Debug for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Condition").field(&self.source).finish()
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Literal(Value),
+    Path(Vec<String>),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Compare(Box<Expr>, Op, Box<Expr>),
+}
+
+impl Expr {
+    fn uses(&self, name: &str) -> bool {
+        match self {
+            Self::Literal(_) => false,
+            Self::Path(path) => path.first().is_some_and(|first| first == name),
+            Self::Not(expr) => expr.uses(name),
+            Self::And(left, right) | Self::Or(left, right) | Self::Compare(left, _, right) => {
+                left.uses(name) || right.uses(name)
+            }
+        }
+    }
+}
+
+impl Expr {
+    fn evaluate(&self, context: &Value) -> Value {
+        match self {
+            Self::Literal(value) => value.clone(),
+            Self::Path(path) => path
+                .iter()
+                .try_fold(context, |value, key| value.get(key))
+                .cloned()
+                .unwrap_or(Value::Null),
+            Self::Not(expr) => Value::Bool(!truthy(&expr.evaluate(context))),
+            Self::And(lhs, rhs) => {
+                Value::Bool(truthy(&lhs.evaluate(context)) && truthy(&rhs.evaluate(context)))
+            }
+            Self::Or(lhs, rhs) => {
+                Value::Bool(truthy(&lhs.evaluate(context)) || truthy(&rhs.evaluate(context)))
+            }
+            Self::Compare(lhs, op, rhs) => {
+                Value::Bool(op.apply(&lhs.evaluate(context), &rhs.evaluate(context)))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Op {
+    fn apply(self, lhs: &Value, rhs: &Value) -> bool {
+        let ordering = compare(lhs, rhs);
+
+        match self {
+            Self::Eq => ordering == Some(Ordering::Equal),
+            Self::Ne => ordering != Some(Ordering::Equal),
+            Self::Lt => ordering == Some(Ordering::Less),
+            Self::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+            Self::Gt => ordering == Some(Ordering::Greater),
+            Self::Ge => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+        }
+    }
+}
+
+/// Compare numbers by value and strings lexicographically, everything else only for equality.
+fn compare(lhs: &Value, rhs: &Value) -> Option<Ordering> {
+    match (lhs, rhs) {
+        (Value::Number(lhs), Value::Number(rhs)) => lhs.as_f64()?.partial_cmp(&rhs.as_f64()?),
+        (Value::String(lhs), Value::String(rhs)) => Some(lhs.cmp(rhs)),
+        (lhs, rhs) if lhs == rhs => Some(Ordering::Equal),
+        _ => None,
+    }
+}
+
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(value) => *value,
+        Value::Number(value) => value.as_f64() != Some(0.0),
+        Value::String(value) => !value.is_empty(),
+        Value::Array(value) => !value.is_empty(),
+        Value::Object(value) => !value.is_empty(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Literal(Value),
+    Ident(String),
+    Dot,
+    Op(Op),
+    Not,
+    And,
+    Or,
+    LParen,
+    RParen,
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        let mut next_is = |expected: char| chars.next_if(|(_, c)| *c == expected).is_some();
+
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '.' => Token::Dot,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '=' if next_is('=') => Token::Op(Op::Eq),
+            '!' if next_is('=') => Token::Op(Op::Ne),
+            '!' => Token::Not,
+            '<' if next_is('=') => Token::Op(Op::Le),
+            '<' => Token::Op(Op::Lt),
+            '>' if next_is('=') => Token::Op(Op::Ge),
+            '>' => Token::Op(Op::Gt),
+            '&' if next_is('&') => Token::And,
+            '|' if next_is('|') => Token::Or,
+            '"' | '\'' => {
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, escaped)) => string.push(escaped),
+                            None => break,
+                        },
+                        Some((_, end)) if end == c => break,
+                        Some((_, c)) => string.push(c),
+                        None => return Err(format!("Unterminated string in '{}'", source)),
+                    }
+                }
+                Token::Literal(Value::String(string))
+            }
+            c if c.is_ascii_digit() || c == '-' => {
+                let mut end = start + c.len_utf8();
+                while let Some((i, c)) = chars.next_if(|(_, c)| c.is_ascii_digit() || *c == '.') {
+                    end = i + c.len_utf8();
+                }
+
+                let number = &source[start..end];
+                let number: f64 = number
+                    .parse()
+                    .map_err(|_| format!("Invalid number '{}' in '{}'", number, source))?;
+                Token::Literal(number.into())
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut end = start + c.len_utf8();
+                while let Some((i, c)) = chars.next_if(|(_, c)| c.is_alphanumeric() || *c == '_') {
+                    end = i + c.len_utf8();
+                }
+
+                match &source[start..end] {
+                    "true" => Token::Literal(Value::Bool(true)),
+                    "false" => Token::Literal(Value::Bool(false)),
+                    "null" => Token::Literal(Value::Null),
+                    ident => Token::Ident(ident.to_string()),
+                }
+            }
+            c => return Err(format!("Unexpected '{}' in '{}'", c, source)),
+        };
+
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        let matches = self.peek() == Some(token);
+        if matches {
+            self.pos += 1;
+        }
+        matches
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.eat(&Token::Or) {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while self.eat(&Token::And) {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat(&Token::Not) {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        let lhs = self.primary()?;
+
+        match self.peek() {
+            Some(Token::Op(op)) => {
+                let op = *op;
+                self.pos += 1;
+                Ok(Expr::Compare(Box::new(lhs), op, Box::new(self.primary()?)))
+            }
+            _ => Ok(lhs),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Literal(value)) => Ok(Expr::Literal(value)),
+            Some(Token::Ident(ident)) => {
+                let mut path = vec![ident];
+                while self.eat(&Token::Dot) {
+                    match self.next() {
+                        Some(Token::Ident(ident)) => path.push(ident),
+                        token => return Err(format!("Expected name after '.', got {:?}", token)),
+                    }
+                }
+                Ok(Expr::Path(path))
+            }
+            Some(Token::LParen) => {
+                let expr = self.or()?;
+                if !self.eat(&Token::RParen) {
+                    return Err("Expected ')'".to_string());
+                }
+                Ok(expr)
+            }
+            token => Err(format!("Expected a value, got {:?}", token)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn condition(source: &str) -> Condition {
+        Condition::try_from(source.to_string()).unwrap()
+    }
+
+    fn evaluate(source: &str) -> bool {
+        let context = json!({
+            "output": "eDP-1",
+            "state": "discharging",
+            "soc": 15,
+            "empty": "",
+            "battery": { "present": true },
+        });
+        condition(source).evaluate(&context)
+    }
+
+    #[test]
+    fn comparisons() {
+        assert!(evaluate(r#"output == "eDP-1""#));
+        assert!(evaluate("output != 'HDMI-A-1'"));
+        assert!(evaluate("soc < 20 && soc <= 15 && soc > -1 && soc >= 15.0"));
+        assert!(evaluate(r#""b" > "a""#));
+        assert!(!evaluate("soc < 10"));
+        // Values of different types are never ordered.
+        assert!(!evaluate("soc < '20'"));
+        assert!(evaluate("soc != '15'"));
+    }
+
+    #[test]
+    fn paths() {
+        assert!(evaluate("battery.present"));
+        assert!(!evaluate("battery.missing"));
+        assert!(!evaluate("missing.present"));
+        assert!(!evaluate("empty"));
+        assert!(evaluate("missing == null"));
+    }
+
+    #[test]
+    fn precedence() {
+        // `&&` binds tighter than `||`.
+        assert!(evaluate("true || false && false"));
+        assert!(!evaluate("(true || false) && false"));
+        // `!` binds tighter than `&&` and applies to a whole comparison.
+        assert!(evaluate("!empty && soc == 15"));
+        assert!(evaluate("!soc == 20"));
+        assert!(!evaluate("!(soc == 15 || empty)"));
+    }
+
+    #[test]
+    fn escaped_strings() {
+        let context = json!({ "title": "say \"hi\"" });
+
+        assert!(condition(r#"title == "say \"hi\"""#).evaluate(&context));
+        assert!(condition(r#"title == 'say "hi"'"#).evaluate(&context));
+    }
+
+    #[test]
+    fn parse_errors() {
+        for source in [
+            "",
+            "soc <",
+            "soc = 1",
+            "(soc",
+            "soc)",
+            "'open",
+            "battery.",
+            "1.2.3",
+            "a & b",
+            "soc == 1 2",
+        ] {
+            assert!(
+                Condition::try_from(source.to_string()).is_err(),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn uses() {
+        let condition = condition("!battery.present || output == 'eDP-1'");
+
+        assert!(condition.uses("battery"));
+        assert!(condition.uses("output"));
+        assert!(!condition.uses("present"));
+        assert!(!condition.uses("eDP-1"));
+    }
+}
@@ -0,0 +1,376 @@
+use std::{future::pending, num::NonZeroU64, process::Stdio};
+
+use gtk::{glib, prelude::*, Label};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::Command,
+    signal::unix::{signal, Signal, SignalKind},
+    sync::{
+        broadcast::{self, error::RecvError},
+        mpsc,
+    },
+    time::Duration,
+};
+use tracing::{error, warn};
+
+use crate::{
+    rbar::RBar,
+    sources::{Publisher, Subscription},
+};
+
+use super::{format::Context, BaseModuleConfig, Events, Module, WidgetContext};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Custom {
+    config: BaseModuleConfig,
+
+    /// Command to run. It is executed with `sh -c`.
+    pub exec: String,
+
+    /// Seconds between two runs of the command, at least 1.
+    ///
+    /// If not set, the command is started once and every line it prints is an update.
+    pub interval: Option<NonZeroU64>,
+
+    /// Run the command immediately when rbar receives `SIGRTMIN+<signal>`,
+    /// e.g. `pkill -RTMIN+8 rbar` for `8`.
+    pub signal: Option<i32>,
+
+    /// Run the command immediately on `rbar msg trigger <trigger>`.
+    pub trigger: Option<String>,
+}
+
+impl Custom {
+    /// Check that `signal` and `trigger` are only used with an `interval`.
+    pub fn validate(&self) -> crate::Result<()> {
+        if self.interval.is_none() && (self.signal.is_some() || self.trigger.is_some()) {
+            return Err(format!(
+                "'{}' runs continuously, 'signal' and 'trigger' require an 'interval'",
+                self.exec
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+}
+
+/// Output of a custom command.
+///
+/// Commands either print plain text or a JSON object with these fields, e.g.
+/// `{"text": "VPN", "tooltip": "Connected to office", "class": "connected"}`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct CustomOutput {
+    #[serde(default)]
+    pub text: String,
+    #[serde(default)]
+    pub tooltip: Option<String>,
+    /// Css classes, either a single class or a list of classes.
+    #[serde(default, deserialize_with = "super::string_or_list")]
+    pub class: Vec<String>,
+    #[serde(default)]
+    pub percentage: Option<f64>,
+}
+
+impl CustomOutput {
+    /// Parse the output of a command.
+    ///
+    /// Falls back to the first line as text if the output is not a JSON object.
+    pub fn parse(output: &str) -> Self {
+        let output = output.trim();
+
+        serde_json::from_str(output).unwrap_or_else(|_| Self {
+            text: output.lines().next().unwrap_or_default().to_string(),
+            ..Default::default()
+        })
+    }
+
+    /// Values available to templates: `{text}`, `{tooltip}` and `{percentage}`.
+    pub fn context(&self) -> Context {
+        let mut context = Context::new()
+            .with("text", self.text.as_str())
+            .with("tooltip", self.tooltip.as_deref().unwrap_or_default());
+        if let Some(percentage) = self.percentage {
+            context.set("percentage", percentage);
+        }
+        context
+    }
+
+    /// Value matched against the states of the module.
+    pub fn value(&self) -> Option<f64> {
+        self.percentage
+    }
+
+    pub fn tooltip(&self) -> Option<String> {
+        self.tooltip.clone()
+    }
+
+    /// Outputs without text hide the module.
+    pub fn is_visible(&self) -> bool {
+        !self.text.is_empty()
+    }
+}
+
+impl Module<Label> for Custom {
+    type Receive = ();
+    type Send = CustomOutput;
+
+    fn name() -> &'static str {
+        "custom"
+    }
+
+    fn controllers(
+        &self,
+        context: &WidgetContext<Self::Send, Self::Receive>,
+        rx: mpsc::Receiver<Self::Receive>,
+    ) -> crate::Result<()> {
+        let exec = self.exec.clone();
+
+        // Run the command once for all modules with the same configuration.
+        let key = format!("{:?}", (&exec, self.interval, self.signal, &self.trigger));
+        let source = match self.interval {
+            Some(interval) => {
+                let duration = Duration::from_secs(interval.get());
+                let signal = self.signal;
+                let trigger = self.trigger.clone();
+
+                RBar::sources().subscribe(key, move |publisher| {
+                    run_interval(exec.clone(), duration, signal, trigger.clone(), publisher)
+                })
+            }
+            None => {
+                // The command is killed once the source stops.
+                RBar::sources().subscribe(key, move |publisher| {
+                    run_continuous(exec.clone(), publisher)
+                })
+            }
+        };
+
+        forward_outputs(context, rx, source, self.exec.clone());
+
+        Ok(())
+    }
+
+    fn widget(&self, context: WidgetContext<Self::Send, Self::Receive>) -> crate::Result<Label> {
+        Ok(output_label(&context))
+    }
+
+    fn format_context(&self, output: &Self::Send) -> Context {
+        output.context()
+    }
+
+    fn value(&self, output: &Self::Send) -> Option<f64> {
+        output.value()
+    }
+
+    fn tooltip(&self, output: &Self::Send) -> Option<String> {
+        output.tooltip()
+    }
+
+    fn classes(&self, output: &Self::Send) -> Vec<String> {
+        output.class.clone()
+    }
+
+    fn is_visible(&self, output: &Self::Send) -> bool {
+        output.is_visible()
+    }
+
+    fn action(
+        &self,
+        name: &str,
+        context: &WidgetContext<Self::Send, Self::Receive>,
+    ) -> crate::Result<()> {
+        match name {
+            "refresh" if self.interval.is_some() => Ok(context.controller_tx.try_send(())?),
+            _ => Err(format!("Module '{}' has no action '{}'", Self::name(), name).into()),
+        }
+    }
+
+    fn get_base_config(&self) -> &BaseModuleConfig {
+        &self.config
+    }
+}
+
+/// Label showing the text of [CustomOutput]s.
+pub fn output_label<R>(context: &WidgetContext<CustomOutput, R>) -> Label {
+    let label = Label::new(None);
+    label.show();
+
+    let widget = label.clone();
+    let mut rx = context.subscribe();
+    glib::spawn_future_local(async move {
+        while let Some(output) = rx.recv().await {
+            label.set_label(&output.text);
+        }
+    });
+
+    widget
+}
+
+/// Send the outputs of a source to the module, refreshing the source whenever
+/// the widget asks to. Used by all modules sending [CustomOutput]s.
+///
+/// Once the source ends, e.g. because the command exited, the module fails and
+/// gets restarted. `name` identifies the source in the error.
+pub fn forward_outputs(
+    context: &WidgetContext<CustomOutput, ()>,
+    mut rx: mpsc::Receiver<()>,
+    mut source: Subscription<Result<CustomOutput, String>>,
+    name: String,
+) {
+    let tx = context.tx.clone();
+    let lifecycle = context.lifecycle.clone();
+    context.lifecycle.spawn(async move {
+        loop {
+            let output = tokio::select! {
+                output = source.next() => output,
+                Some(()) = rx.recv() => {
+                    source.refresh();
+                    continue;
+                }
+            };
+
+            let Some(output) = output else {
+                lifecycle.fail(format!("'{}' stopped", name));
+                break;
+            };
+
+            let event = match output {
+                Ok(output) => Events::Update(output),
+                Err(e) => Events::Error(e),
+            };
+
+            if let Err(e) = tx.send(event).await {
+                error!("Failed to send output of '{}': {}", name, e);
+                break;
+            }
+            lifecycle.running().await;
+        }
+    });
+}
+
+/// Source running the command every `duration` and sending its whole output.
+async fn run_interval(
+    exec: String,
+    duration: Duration,
+    signal: Option<i32>,
+    trigger: Option<String>,
+    publisher: Publisher<Result<CustomOutput, String>>,
+) {
+    let mut refresh = Refresh::new(signal, trigger).unwrap_or_else(|e| {
+        error!("Failed to set up refresh for '{}': {}", exec, e);
+        Refresh::default()
+    });
+
+    loop {
+        let output = match command(&exec).output().await {
+            Ok(output) if output.status.success() => Ok(CustomOutput::parse(
+                &String::from_utf8_lossy(&output.stdout),
+            )),
+            Ok(output) => Err(format!("'{}' exited with {}", exec, output.status)),
+            Err(e) => Err(format!("Failed to run '{}': {}", exec, e)),
+        };
+        publisher.send(output);
+
+        tokio::select! {
+            _ = publisher.sleep(duration) => {}
+            _ = refresh.wait() => {}
+        }
+    }
+}
+
+/// [Refresh] requests an immediate run of an interval command.
+#[derive(Default)]
+struct Refresh {
+    signal: Option<Signal>,
+    trigger: Option<(String, broadcast::Receiver<String>)>,
+}
+
+impl Refresh {
+    /// Listen for `SIGRTMIN+signal` and the named trigger.
+    ///
+    /// Must be called from within [RBar::runtime].
+    fn new(signal_offset: Option<i32>, trigger: Option<String>) -> crate::Result<Self> {
+        let signal = match signal_offset {
+            Some(offset) => {
+                let signum = libc::SIGRTMIN() + offset;
+                if offset < 0 || signum > libc::SIGRTMAX() {
+                    return Err(format!("Invalid signal SIGRTMIN+{}", offset).into());
+                }
+
+                Some(signal(SignalKind::from_raw(signum))?)
+            }
+            None => None,
+        };
+
+        let trigger = trigger.map(|name| (name, RBar::triggers().subscribe()));
+
+        Ok(Self { signal, trigger })
+    }
+
+    /// Wait until a refresh is requested.
+    async fn wait(&mut self) {
+        let Self { signal, trigger } = self;
+
+        let signal = async {
+            let Some(signal) = signal else {
+                return pending().await;
+            };
+
+            if signal.recv().await.is_none() {
+                pending().await
+            }
+        };
+
+        let trigger = async {
+            let Some((name, rx)) = trigger else {
+                return pending().await;
+            };
+
+            loop {
+                match rx.recv().await {
+                    Ok(triggered) if triggered == *name => break,
+                    Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => pending().await,
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = signal => {}
+            _ = trigger => {}
+        }
+    }
+}
+
+/// Source running the command once and sending every line it prints.
+async fn run_continuous(exec: String, publisher: Publisher<Result<CustomOutput, String>>) {
+    let mut child = match command(&exec).stdout(Stdio::piped()).spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            publisher.send(Err(format!("Failed to run '{}': {}", exec, e)));
+            return;
+        }
+    };
+
+    let Some(stdout) = child.stdout.take() else {
+        return;
+    };
+
+    let mut lines = BufReader::new(stdout).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        publisher.send(Ok(CustomOutput::parse(&line)));
+    }
+
+    match child.wait().await {
+        Ok(status) => warn!("'{}' exited with {}", exec, status),
+        Err(e) => error!("Failed to wait for '{}': {}", exec, e),
+    }
+}
+
+fn command(exec: &str) -> Command {
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(exec).kill_on_drop(true);
+    command
+}
@@ -0,0 +1,185 @@
+//! Widgets showing the value of numeric modules, in percent.
+//!
+//! The widget is shown next to a module with a `display` option, and fed the
+//! same value as its `states`, see [Module::value](super::Module::value). The
+//! widgets are drawn in the text color of the module, so they are styled with css.
+
+use std::{cell::RefCell, collections::VecDeque, f64::consts::PI, rc::Rc};
+
+use gtk::{cairo, prelude::*, DrawingArea, LevelBar, LevelBarMode, Widget};
+use serde::Deserialize;
+
+/// Number of values shown in a graph.
+const HISTORY: usize = 30;
+
+/// How a module shows its value.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Display {
+    /// Only the text of the module.
+    #[default]
+    Text,
+    /// A level bar.
+    Bar,
+    /// A graph of the recent values.
+    Graph,
+    /// A circular gauge.
+    Circle,
+}
+
+/// [Indicator] is the widget of a [Display] other than [Display::Text].
+#[derive(Debug, Clone)]
+pub enum Indicator {
+    Bar(LevelBar),
+    Graph(DrawingArea, Rc<RefCell<VecDeque<f64>>>),
+    Circle(DrawingArea, Rc<RefCell<f64>>),
+}
+
+impl Indicator {
+    /// Create the widget for the display, `None` for [Display::Text].
+    pub fn new(display: Display) -> Option<Self> {
+        let indicator = match display {
+            Display::Text => return None,
+            Display::Bar => Self::bar(),
+            Display::Graph => Self::graph(),
+            Display::Circle => Self::circle(),
+        };
+
+        let widget = indicator.widget();
+        widget.add_css_class("indicator");
+        widget.set_valign(gtk::Align::Center);
+
+        Some(indicator)
+    }
+
+    fn bar() -> Self {
+        let bar = LevelBar::builder()
+            .min_value(0.0)
+            .max_value(100.0)
+            .mode(LevelBarMode::Continuous)
+            .width_request(40)
+            .build();
+
+        // Colored by the states of the module instead.
+        for offset in [
+            gtk::LEVEL_BAR_OFFSET_LOW,
+            gtk::LEVEL_BAR_OFFSET_HIGH,
+            gtk::LEVEL_BAR_OFFSET_FULL,
+        ] {
+            bar.remove_offset_value(Some(offset));
+        }
+
+        Self::Bar(bar)
+    }
+
+    fn graph() -> Self {
+        let area = DrawingArea::builder()
+            .content_width(HISTORY as i32 * 2)
+            .content_height(16)
+            .build();
+        let history = Rc::new(RefCell::new(VecDeque::with_capacity(HISTORY)));
+
+        area.set_draw_func({
+            let history = history.clone();
+            move |area, cr, width, height| {
+                set_color(area, cr, 1.0);
+                draw_graph(cr, &history.borrow(), width.into(), height.into());
+            }
+        });
+
+        Self::Graph(area, history)
+    }
+
+    fn circle() -> Self {
+        let area = DrawingArea::builder()
+            .content_width(16)
+            .content_height(16)
+            .build();
+        let value = Rc::new(RefCell::new(0.0));
+
+        area.set_draw_func({
+            let value = value.clone();
+            move |area, cr, width, height| {
+                draw_circle(area, cr, *value.borrow(), width.into(), height.into());
+            }
+        });
+
+        Self::Circle(area, value)
+    }
+
+    pub fn widget(&self) -> Widget {
+        match self {
+            Self::Bar(bar) => bar.clone().upcast(),
+            Self::Graph(area, _) | Self::Circle(area, _) => area.clone().upcast(),
+        }
+    }
+
+    /// Show a new value in percent.
+    pub fn set(&self, value: f64) {
+        let value = value.clamp(0.0, 100.0);
+
+        match self {
+            Self::Bar(bar) => bar.set_value(value),
+            Self::Graph(area, history) => {
+                let mut history = history.borrow_mut();
+                if history.len() == HISTORY {
+                    history.pop_front();
+                }
+                history.push_back(value);
+                area.queue_draw();
+            }
+            Self::Circle(area, current) => {
+                *current.borrow_mut() = value;
+                area.queue_draw();
+            }
+        }
+    }
+}
+
+/// Use the text color of the widget.
+fn set_color(widget: &impl IsA<Widget>, cr: &cairo::Context, alpha: f64) {
+    let color = widget.style_context().color();
+    cr.set_source_rgba(
+        color.red().into(),
+        color.green().into(),
+        color.blue().into(),
+        f64::from(color.alpha()) * alpha,
+    );
+}
+
+/// Fill the area below the values, the latest on the right.
+fn draw_graph(cr: &cairo::Context, history: &VecDeque<f64>, width: f64, height: f64) {
+    if history.is_empty() {
+        return;
+    }
+
+    let step = width / (HISTORY - 1) as f64;
+    let start = width - step * (history.len() - 1) as f64;
+
+    cr.move_to(start, height);
+    for (i, value) in history.iter().enumerate() {
+        cr.line_to(start + step * i as f64, height - height * value / 100.0);
+    }
+    cr.line_to(width, height);
+    cr.close_path();
+
+    // Nothing to do if drawing fails, the next value redraws the graph.
+    let _ = cr.fill();
+}
+
+/// Draw a faint ring with the value as an arc on top, starting at the top.
+fn draw_circle(area: &DrawingArea, cr: &cairo::Context, value: f64, width: f64, height: f64) {
+    let line_width = 3.0;
+    let radius = (width.min(height) - line_width) / 2.0;
+    let (x, y) = (width / 2.0, height / 2.0);
+    cr.set_line_width(line_width);
+
+    set_color(area, cr, 0.3);
+    cr.arc(x, y, radius, 0.0, 2.0 * PI);
+    let _ = cr.stroke();
+
+    set_color(area, cr, 1.0);
+    let start = -PI / 2.0;
+    cr.arc(x, y, radius, start, start + 2.0 * PI * value / 100.0);
+    let _ = cr.stroke();
+}
@@ -0,0 +1,480 @@
+//! Format templates shared by all modules.
+//!
+//! A [Template] is text with named placeholders that modules fill from a [Context]:
+//!
+//! - `{name}` inserts a value, `{name:spec}` formats it: `.N` is the precision of
+//!   numbers (`{percentage:.1}`), times take a strftime format (`{time:%H:%M}`).
+//! - `{?name}...{/name}` is only shown if `name` is set and truthy,
+//!   `{!name}...{/name}` only if it is not.
+//! - `{{` and `}}` insert literal braces.
+//!
+//! Templates are Pango markup. Inserted values are escaped, so only the template
+//! itself can contain markup, e.g. `<b>{percentage:.0}%</b>`.
+
+use std::{collections::HashMap, fmt::Write};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Deserializer};
+
+/// The maximum precision of numbers, higher ones are clamped.
+const MAX_PRECISION: usize = 20;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+    Text(String),
+    Placeholder {
+        name: String,
+        spec: Option<String>,
+    },
+    Section {
+        name: String,
+        negate: bool,
+        parts: Vec<Part>,
+    },
+}
+
+impl Template {
+    /// Create a template of a single placeholder, e.g. `{time:%H:%M}` without any escaping of the spec.
+    pub fn placeholder(name: &str, spec: Option<&str>) -> Self {
+        Self {
+            parts: vec![Part::Placeholder {
+                name: name.to_string(),
+                spec: spec.map(str::to_string),
+            }],
+        }
+    }
+
+    /// Get the specs of all placeholders with the given name, `None` if one has no spec.
+    pub fn specs(&self, name: &str) -> Vec<Option<&str>> {
+        let mut specs = Vec::new();
+        collect_specs(&self.parts, name, &mut specs);
+        specs
+    }
+
+    /// Render the template with the values of the context.
+    pub fn render(&self, context: &Context) -> String {
+        let mut output = String::new();
+        render_parts(&self.parts, context, &mut output);
+        output
+    }
+}
+
+impl TryFrom<String> for Template {
+    type Error = String;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        source.parse()
+    }
+}
+
+impl std::str::FromStr for Template {
+    type Err = String;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let mut chars = source.chars().peekable();
+        let parts = parse_parts(&mut chars, None)?;
+        Ok(Self { parts })
+    }
+}
+
+fn parse_parts(
+    chars: &mut std::iter::Peekable<std::str::Chars>,
+    section: Option<&str>,
+) -> Result<Vec<Part>, String> {
+    let mut parts = Vec::new();
+    let mut text = String::new();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.next_if_eq(&'{').is_some() => text.push('{'),
+            '}' if chars.next_if_eq(&'}').is_some() => text.push('}'),
+            '}' => return Err("Unexpected '}', use '}}' for a literal brace".to_string()),
+            '{' => {
+                let mut tag = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => tag.push(c),
+                        None => return Err(format!("Unterminated placeholder '{{{}'", tag)),
+                    }
+                }
+
+                if !text.is_empty() {
+                    parts.push(Part::Text(std::mem::take(&mut text)));
+                }
+
+                if let Some(name) = tag.strip_prefix('/') {
+                    return match section {
+                        Some(section) if section == name => Ok(parts),
+                        _ => Err(format!("Unexpected '{{/{}}}'", name)),
+                    };
+                }
+
+                let (negate, name) = match tag.chars().next() {
+                    Some('?') => (Some(false), &tag[1..]),
+                    Some('!') => (Some(true), &tag[1..]),
+                    _ => (None, tag.as_str()),
+                };
+
+                let part = match negate {
+                    Some(negate) => Part::Section {
+                        name: name.to_string(),
+                        negate,
+                        parts: parse_parts(chars, Some(name))?,
+                    },
+                    None => match name.split_once(':') {
+                        Some((name, spec)) => Part::Placeholder {
+                            name: name.to_string(),
+                            spec: Some(spec.to_string()),
+                        },
+                        None => Part::Placeholder {
+                            name: name.to_string(),
+                            spec: None,
+                        },
+                    },
+                };
+                parts.push(part);
+            }
+            c => text.push(c),
+        }
+    }
+
+    if let Some(section) = section {
+        return Err(format!("Missing '{{/{}}}'", section));
+    }
+
+    if !text.is_empty() {
+        parts.push(Part::Text(text));
+    }
+
+    Ok(parts)
+}
+
+fn collect_specs<'a>(parts: &'a [Part], name: &str, specs: &mut Vec<Option<&'a str>>) {
+    for part in parts {
+        match part {
+            Part::Placeholder { name: part, spec } if part == name => specs.push(spec.as_deref()),
+            Part::Section { parts, .. } => collect_specs(parts, name, specs),
+            _ => {}
+        }
+    }
+}
+
+fn render_parts(parts: &[Part], context: &Context, output: &mut String) {
+    for part in parts {
+        match part {
+            Part::Text(text) => output.push_str(text),
+            Part::Placeholder { name, spec } => {
+                if let Some(arg) = context.get(name) {
+                    output.push_str(&escape(&arg.format(spec.as_deref())));
+                }
+            }
+            Part::Section {
+                name,
+                negate,
+                parts,
+            } => {
+                let truthy = context.get(name).is_some_and(Arg::is_truthy);
+                if truthy != *negate {
+                    render_parts(parts, context, output);
+                }
+            }
+        }
+    }
+}
+
+/// Escape text for Pango markup.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '\'' => escaped.push_str("&#39;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Values available to a [Template].
+#[derive(Debug, Clone, Default)]
+pub struct Context {
+    args: HashMap<String, Arg>,
+}
+
+impl Context {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a value to the context.
+    pub fn with(mut self, name: &str, arg: impl Into<Arg>) -> Self {
+        self.set(name, arg);
+        self
+    }
+
+    /// Set a value of the context.
+    pub fn set(&mut self, name: &str, arg: impl Into<Arg>) {
+        self.args.insert(name.to_string(), arg.into());
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Arg> {
+        self.args.get(name)
+    }
+}
+
+/// A value of a [Context].
+#[derive(Debug, Clone)]
+pub enum Arg {
+    Text(String),
+    Number(f64),
+    Bool(bool),
+    Time(DateTime<Local>),
+}
+
+impl Arg {
+    fn format(&self, spec: Option<&str>) -> String {
+        match self {
+            Self::Text(text) => text.clone(),
+            Self::Number(number) => match spec
+                .and_then(|spec| spec.strip_prefix('.'))
+                .and_then(|precision| precision.parse::<usize>().ok())
+            {
+                // Precisions above u16::MAX panic in the formatter.
+                Some(precision) => format!("{:.1$}", number, precision.min(MAX_PRECISION)),
+                None => number.to_string(),
+            },
+            Self::Bool(value) => value.to_string(),
+            Self::Time(time) => {
+                let mut output = String::new();
+                // Invalid strftime formats fail while writing.
+                match write!(output, "{}", time.format(spec.unwrap_or("%H:%M"))) {
+                    Ok(()) => output,
+                    Err(_) => format!("invalid time format '{}'", spec.unwrap_or_default()),
+                }
+            }
+        }
+    }
+
+    fn is_truthy(&self) -> bool {
+        match self {
+            Self::Text(text) => !text.is_empty(),
+            Self::Number(number) => *number != 0.0,
+            Self::Bool(value) => *value,
+            Self::Time(_) => true,
+        }
+    }
+}
+
+impl From<String> for Arg {
+    fn from(text: String) -> Self {
+        Self::Text(text)
+    }
+}
+
+impl From<&str> for Arg {
+    fn from(text: &str) -> Self {
+        Self::Text(text.to_string())
+    }
+}
+
+impl From<f64> for Arg {
+    fn from(number: f64) -> Self {
+        Self::Number(number)
+    }
+}
+
+impl From<f32> for Arg {
+    fn from(number: f32) -> Self {
+        Self::Number(number.into())
+    }
+}
+
+impl From<bool> for Arg {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+impl From<DateTime<Local>> for Arg {
+    fn from(time: DateTime<Local>) -> Self {
+        Self::Time(time)
+    }
+}
+
+/// Icons picked by a value between `0` and `100`.
+///
+/// Either a list of icons spread evenly over the range, e.g. `["", "", ""]`,
+/// or a map from the upper bound of each step to its icon, e.g. `{ "10": "", "100": "" }`.
+#[derive(Debug, Clone)]
+pub struct IconRamp {
+    steps: Vec<(f64, String)>,
+}
+
+impl IconRamp {
+    /// Spread icons evenly between `0` and `100`.
+    pub fn even<S: Into<String>>(icons: impl IntoIterator<Item = S>) -> Self {
+        let icons: Vec<String> = icons.into_iter().map(Into::into).collect();
+        let step = 100.0 / icons.len().max(1) as f64;
+
+        let steps = icons
+            .into_iter()
+            .enumerate()
+            .map(|(i, icon)| ((i + 1) as f64 * step, icon))
+            .collect();
+
+        Self { steps }
+    }
+
+    /// Use explicit steps, each given by the upper bound of its range and its icon.
+    pub fn steps<S: Into<String>>(steps: impl IntoIterator<Item = (f64, S)>) -> Self {
+        let mut steps: Vec<(f64, String)> = steps
+            .into_iter()
+            .map(|(max, icon)| (max, icon.into()))
+            .collect();
+        steps.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+
+        Self { steps }
+    }
+
+    /// Get the icon for a value. Values above the last step use the last icon.
+    pub fn get(&self, value: f64) -> &str {
+        self.steps
+            .iter()
+            .find(|(max, _)| value <= *max)
+            .or(self.steps.last())
+            .map_or("", |(_, icon)| icon.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for IconRamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Ramp {
+            Even(Vec<String>),
+            Steps(HashMap<String, String>),
+        }
+
+        match Ramp::deserialize(deserializer)? {
+            Ramp::Even(icons) => Ok(Self::even(icons)),
+            Ramp::Steps(steps) => {
+                let steps = steps
+                    .into_iter()
+                    .map(|(max, icon)| {
+                        max.parse::<f64>().map(|max| (max, icon)).map_err(|_| {
+                            serde::de::Error::custom(format!("Invalid step '{}'", max))
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                Ok(Self::steps(steps))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &str, context: &Context) -> String {
+        template.parse::<Template>().unwrap().render(context)
+    }
+
+    #[test]
+    fn placeholders() {
+        let context = Context::new().with("name", "bar").with("value", 42.125);
+
+        assert_eq!(render("{name}: {value}", &context), "bar: 42.125");
+        assert_eq!(render("{value:.1}", &context), "42.1");
+        assert_eq!(render("{value:.0}%", &context), "42%");
+        assert_eq!(render("[{missing}]", &context), "[]");
+    }
+
+    #[test]
+    fn precision_is_clamped() {
+        let context = Context::new().with("value", 0.5);
+
+        assert_eq!(
+            render("{value:.99999999}", &context),
+            format!("{:.20}", 0.5)
+        );
+    }
+
+    #[test]
+    fn time_spec() {
+        let time = Local::now();
+        let context = Context::new().with("time", time);
+
+        assert_eq!(render("{time}", &context), time.format("%H:%M").to_string());
+        assert_eq!(render("{time:%Y}", &context), time.format("%Y").to_string());
+    }
+
+    #[test]
+    fn sections() {
+        let context = Context::new()
+            .with("charging", true)
+            .with("empty", "")
+            .with("zero", 0.0);
+
+        assert_eq!(render("{?charging}+{/charging}", &context), "+");
+        assert_eq!(render("{!charging}-{/charging}", &context), "");
+        assert_eq!(render("{?empty}x{/empty}{?zero}y{/zero}", &context), "");
+        assert_eq!(render("{!missing}none{/missing}", &context), "none");
+        assert_eq!(
+            render("{?charging}a{!zero}b{/zero}{/charging}", &context),
+            "ab"
+        );
+    }
+
+    #[test]
+    fn escaping() {
+        let context = Context::new().with("title", "<Tom & \"Jerry's\">");
+
+        assert_eq!(
+            render("{{{title}}}", &context),
+            "{&lt;Tom &amp; &quot;Jerry&#39;s&quot;&gt;}"
+        );
+        assert_eq!(render("<b>{title}</b>", &context).find("<b>"), Some(0));
+    }
+
+    #[test]
+    fn parse_errors() {
+        for template in ["{name", "}", "{?a}x", "{?a}x{/b}", "x{/a}"] {
+            assert!(template.parse::<Template>().is_err(), "{}", template);
+        }
+    }
+
+    #[test]
+    fn specs() {
+        let template: Template = "{time:%H} {?a}{time}{/a} {other:.1}".parse().unwrap();
+
+        assert_eq!(template.specs("time"), [Some("%H"), None]);
+    }
+
+    #[test]
+    fn icon_ramp() {
+        let even = IconRamp::even(["a", "b", "c", "d"]);
+        assert_eq!(even.get(0.0), "a");
+        assert_eq!(even.get(25.0), "a");
+        assert_eq!(even.get(26.0), "b");
+        assert_eq!(even.get(100.0), "d");
+        assert_eq!(even.get(150.0), "d");
+
+        let steps: IconRamp = serde_json::from_str(r#"{ "100": "full", "10": "low" }"#).unwrap();
+        assert_eq!(steps.get(5.0), "low");
+        assert_eq!(steps.get(50.0), "full");
+
+        assert!(serde_json::from_str::<IconRamp>(r#"{ "ten": "low" }"#).is_err());
+    }
+}
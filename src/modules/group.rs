@@ -0,0 +1,194 @@
+use gtk::{
+    prelude::*, EventControllerMotion, GestureClick, Label, Orientation, Revealer,
+    RevealerTransitionType,
+};
+use serde::Deserialize;
+use tracing::error;
+
+use crate::bar::Bar;
+
+use super::{
+    BaseModuleConfig, Module, ModuleFactory, ModuleHandle, ModulePosition, Modules, WidgetContext,
+};
+
+/// A group of modules, styled and optionally collapsed together.
+///
+/// Like any module, the group is styled with the `id` and `classes` of its `config`.
+///
+/// Example: `{"name": "group", "config": {"position": "right", "classes": "system"}, "drawer": {"label": "⚙"}, "modules": [...]}`
+#[derive(Debug, Clone, Deserialize)]
+pub struct Group {
+    config: BaseModuleConfig,
+
+    /// Modules in the group. They are shown at the position of the group and
+    /// don't need a `position` of their own.
+    pub modules: Vec<Modules>,
+
+    /// Collapse the modules into a drawer.
+    #[serde(default)]
+    pub drawer: Option<Drawer>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Drawer {
+    /// Expand the drawer on hover or on click.
+    #[serde(default)]
+    pub reveal_on: Reveal,
+
+    /// Label shown next to the drawer, which is always visible.
+    #[serde(default)]
+    pub label: Option<String>,
+
+    /// Duration of the animation in milliseconds.
+    #[serde(default = "default_transition_duration")]
+    pub transition_duration: u32,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reveal {
+    #[default]
+    Hover,
+    Click,
+}
+
+fn default_transition_duration() -> u32 {
+    250
+}
+
+impl Group {
+    /// Name of the module.
+    pub const NAME: &'static str = "group";
+
+    /// Create the group and all its modules.
+    pub fn create(&self, factory: &ModuleFactory, bar: &Bar) -> crate::Result<Vec<ModuleHandle>> {
+        let content = gtk::Box::new(Orientation::Horizontal, 0);
+        content.add_css_class("content");
+
+        let instance = Instance {
+            group: self.clone(),
+            content: content.clone(),
+        };
+        let Some(handle) = factory.create(&instance, bar)? else {
+            return Ok(Vec::new());
+        };
+
+        let factory = factory.with_container(content, handle.position);
+
+        let mut handles = vec![handle];
+        for module in Modules::sorted(&self.modules) {
+            match module.create(&factory, bar) {
+                Ok(children) => handles.extend(children),
+                Err(e) => error!("Failed to load module in group: {}", e),
+            }
+        }
+
+        Ok(handles)
+    }
+
+    /// Get the configuration shared by all modules.
+    pub fn get_base_config(&self) -> &BaseModuleConfig {
+        &self.config
+    }
+}
+
+/// A [Group] on a bar, with the box its modules are added to.
+#[derive(Debug, Clone)]
+struct Instance {
+    group: Group,
+    content: gtk::Box,
+}
+
+impl Module<gtk::Box> for Instance {
+    type Receive = ();
+    type Send = ();
+
+    fn name() -> &'static str {
+        Group::NAME
+    }
+
+    fn widget(
+        &self,
+        _context: WidgetContext<Self::Send, Self::Receive>,
+    ) -> crate::Result<gtk::Box> {
+        let container = gtk::Box::new(Orientation::Horizontal, 0);
+
+        let revealer = Revealer::builder()
+            .child(&self.content)
+            .reveal_child(self.group.drawer.is_none())
+            .build();
+
+        let Some(drawer) = &self.group.drawer else {
+            container.append(&revealer);
+            return Ok(container);
+        };
+
+        container.add_css_class("drawer");
+
+        // Slide away from the edge of the bar.
+        revealer.set_transition_duration(drawer.transition_duration);
+        revealer.set_transition_type(match self.get_position() {
+            Some(ModulePosition::Right) => RevealerTransitionType::SlideLeft,
+            _ => RevealerTransitionType::SlideRight,
+        });
+
+        let label = Label::new(drawer.label.as_deref());
+        label.add_css_class("label");
+        label.set_visible(drawer.label.is_some());
+
+        // The label leads into the drawer.
+        match self.get_position() {
+            Some(ModulePosition::Right) => {
+                container.append(&revealer);
+                container.append(&label);
+            }
+            _ => {
+                container.append(&label);
+                container.append(&revealer);
+            }
+        }
+
+        let expand = {
+            let container = container.clone();
+            move |expanded: bool| {
+                revealer.set_reveal_child(expanded);
+                if expanded {
+                    container.add_css_class("expanded");
+                } else {
+                    container.remove_css_class("expanded");
+                }
+            }
+        };
+
+        match drawer.reveal_on {
+            Reveal::Hover => {
+                let controller = EventControllerMotion::new();
+                controller.connect_enter({
+                    let expand = expand.clone();
+                    move |_, _, _| expand(true)
+                });
+                controller.connect_leave(move |_| expand(false));
+                container.add_controller(controller);
+            }
+            Reveal::Click => {
+                let gesture = GestureClick::new();
+                gesture.connect_released({
+                    let container = container.clone();
+                    move |_, _, _, _| expand(!container.has_css_class("expanded"))
+                });
+
+                // Without a label, the whole group is clicked to toggle it.
+                match drawer.label {
+                    Some(_) => label.add_controller(gesture),
+                    None => container.add_controller(gesture),
+                }
+            }
+        }
+
+        Ok(container)
+    }
+
+    fn get_base_config(&self) -> &BaseModuleConfig {
+        &self.group.config
+    }
+}
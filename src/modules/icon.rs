@@ -0,0 +1,326 @@
+//! Icons of modules: font glyphs, SVGs or icons of the icon theme.
+//!
+//! Icons are given as text. `svg:<name>` is an SVG bundled with rbar, text
+//! ending in `.svg` a user SVG, relative to `~/.config/rbar/icons`, and
+//! `icon:<name>` is looked up in the GTK icon theme, e.g.
+//! `icon:battery-level-40-symbolic`. Any other text is a glyph.
+//!
+//! SVGs are drawn in the text color of the widget by replacing `currentColor`,
+//! and rendered at the scale factor of the output. Symbolic theme icons follow
+//! the text color as well.
+//!
+//! The `icons` of a module configuration replace the `{icon}` of its templates
+//! by text, e.g. `{"icon:battery-level-40-symbolic": "svg:battery"}`, see [replace].
+
+use std::{cell::RefCell, collections::HashMap, fs, path::PathBuf, rc::Rc};
+
+use gtk::{cairo, gdk::RGBA, prelude::*, DrawingArea, Image, Label, Orientation};
+use resvg::{tiny_skia, usvg};
+use tracing::warn;
+
+use crate::config::Config;
+
+use super::format::{Arg, Context};
+
+/// SVGs bundled with rbar, by name.
+const BUNDLED: &[(&str, &str)] = &[("battery", include_str!("../assets/battery.svg"))];
+
+/// Default size of SVG icons in logical pixels.
+const SIZE: i32 = 16;
+
+/// [Icon] is a source of an icon.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Icon {
+    Glyph(String),
+    Svg(String),
+    /// Name of an icon of the icon theme.
+    Themed(String),
+}
+
+impl Icon {
+    /// Get the icon for its text, see the [module docs](self).
+    pub fn parse(text: &str) -> Self {
+        if let Some(name) = text.strip_prefix("svg:") {
+            return match bundled(name) {
+                Some(svg) => Self::Svg(svg.to_string()),
+                None => {
+                    warn!("No bundled icon '{}'", name);
+                    Self::Glyph(String::new())
+                }
+            };
+        }
+
+        if text.ends_with(".svg") {
+            let path = Config::get_dir().join("icons").join(PathBuf::from(text));
+            return match fs::read_to_string(&path) {
+                Ok(svg) => Self::Svg(svg),
+                Err(e) => {
+                    warn!("Failed to read icon '{}': {}", path.display(), e);
+                    Self::Glyph(String::new())
+                }
+            };
+        }
+
+        if let Some(name) = text.strip_prefix("icon:") {
+            return Self::Themed(name.to_string());
+        }
+
+        Self::Glyph(text.to_string())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        matches!(self, Self::Glyph(glyph) if glyph.is_empty())
+    }
+}
+
+/// Get a bundled SVG by name.
+pub fn bundled(name: &str) -> Option<&'static str> {
+    BUNDLED
+        .iter()
+        .find(|(bundled, _)| *bundled == name)
+        .map(|(_, svg)| *svg)
+}
+
+/// Replace the `{icon}` of a template context if it has an override.
+pub fn replace(context: &mut Context, overrides: &HashMap<String, String>) {
+    let replacement = match context.get("icon") {
+        Some(Arg::Text(icon)) => overrides.get(icon),
+        _ => None,
+    };
+
+    if let Some(replacement) = replacement {
+        context.set("icon", replacement.clone());
+    }
+}
+
+/// A battery filled up to the state of charge, in percent.
+pub fn battery(percentage: f64) -> Icon {
+    let svg = bundled("battery").unwrap_or_default();
+
+    // The level is drawn inside the body, which leaves 22 units at x = 5.
+    let level = format!(
+        r#"<rect x="5" y="11" width="{:.1}" height="14" rx="1" fill="currentColor" />"#,
+        22.0 * percentage.clamp(0.0, 100.0) / 100.0
+    );
+
+    Icon::Svg(svg.replace("</svg>", &format!("{}</svg>", level)))
+}
+
+/// [IconWidget] shows an [Icon].
+#[derive(Debug, Clone)]
+pub struct IconWidget {
+    container: gtk::Box,
+    label: Label,
+    area: DrawingArea,
+    image: Image,
+    svg: Rc<RefCell<Svg>>,
+    /// Text of the icon, see [IconWidget::set_text].
+    text: Rc<RefCell<Option<String>>>,
+}
+
+/// An SVG and its tree, parsed for the color it was last drawn in.
+#[derive(Debug, Default)]
+struct Svg {
+    source: String,
+    tree: Option<(RGBA, usvg::Tree)>,
+}
+
+impl IconWidget {
+    pub fn new() -> Self {
+        let container = gtk::Box::new(Orientation::Horizontal, 0);
+        container.add_css_class("icon");
+
+        let label = Label::new(None);
+        let area = DrawingArea::builder()
+            .content_width(SIZE)
+            .content_height(SIZE)
+            .valign(gtk::Align::Center)
+            .visible(false)
+            .build();
+        let image = Image::builder().pixel_size(SIZE).visible(false).build();
+
+        container.append(&label);
+        container.append(&area);
+        container.append(&image);
+
+        let svg = Rc::new(RefCell::new(Svg::default()));
+        area.set_draw_func({
+            let svg = svg.clone();
+            move |area, cr, width, height| {
+                let color = area.style_context().color();
+                if let Err(e) = svg.borrow_mut().draw(area, cr, color, width, height) {
+                    warn!("Failed to draw icon: {}", e);
+                }
+            }
+        });
+
+        Self {
+            container,
+            label,
+            area,
+            image,
+            svg,
+            text: Default::default(),
+        }
+    }
+
+    pub fn widget(&self) -> &gtk::Box {
+        &self.container
+    }
+
+    /// Show the icon for the text, see [Icon::parse]. Only parsed if the text changed.
+    pub fn set_text(&self, text: &str) {
+        if self.text.borrow().as_deref() == Some(text) {
+            return;
+        }
+
+        self.show(&Icon::parse(text));
+        *self.text.borrow_mut() = Some(text.to_string());
+    }
+
+    /// Show the icon, hiding the widget if it is empty.
+    pub fn set(&self, icon: &Icon) {
+        *self.text.borrow_mut() = None;
+        self.show(icon);
+    }
+
+    fn show(&self, icon: &Icon) {
+        self.container.set_visible(!icon.is_empty());
+
+        self.label.set_visible(matches!(icon, Icon::Glyph(_)));
+        self.area.set_visible(matches!(icon, Icon::Svg(_)));
+        self.image.set_visible(matches!(icon, Icon::Themed(_)));
+
+        match icon {
+            Icon::Glyph(glyph) => self.label.set_label(glyph),
+            Icon::Svg(source) => {
+                let mut svg = self.svg.borrow_mut();
+                if svg.source != *source {
+                    svg.source.clone_from(source);
+                    svg.tree = None;
+                    self.area.queue_draw();
+                }
+            }
+            Icon::Themed(name) => self.image.set_icon_name(Some(name)),
+        }
+    }
+}
+
+impl Svg {
+    fn draw(
+        &mut self,
+        area: &DrawingArea,
+        cr: &cairo::Context,
+        color: RGBA,
+        width: i32,
+        height: i32,
+    ) -> crate::Result<()> {
+        let tree = match &self.tree {
+            Some((drawn, tree)) if *drawn == color => tree,
+            _ => {
+                let source = self.source.replace("currentColor", &hex(&color));
+                let tree = usvg::Tree::from_str(&source, &usvg::Options::default())?;
+                &self.tree.insert((color, tree)).1
+            }
+        };
+
+        // Render at the resolution of the output.
+        let scale = area.scale_factor();
+        let (pixel_width, pixel_height) = (width * scale, height * scale);
+        let mut pixmap = tiny_skia::Pixmap::new(pixel_width as u32, pixel_height as u32)
+            .ok_or("Icon has no size")?;
+
+        // Fit the icon into the area, centered.
+        let size = tree.size();
+        let fit = (pixel_width as f32 / size.width()).min(pixel_height as f32 / size.height());
+        let transform = tiny_skia::Transform::from_scale(fit, fit).post_translate(
+            (pixel_width as f32 - size.width() * fit) / 2.0,
+            (pixel_height as f32 - size.height() * fit) / 2.0,
+        );
+        resvg::render(tree, transform, &mut pixmap.as_mut());
+
+        // Cairo wants premultiplied BGRA on little endian, tiny-skia has RGBA.
+        let mut data = pixmap.take();
+        for pixel in data.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+
+        let stride = pixel_width * 4;
+        let surface = cairo::ImageSurface::create_for_data(
+            data,
+            cairo::Format::ARgb32,
+            pixel_width,
+            pixel_height,
+            stride,
+        )?;
+        surface.set_device_scale(scale.into(), scale.into());
+
+        cr.set_source_surface(&surface, 0.0, 0.0)?;
+        cr.paint()?;
+
+        Ok(())
+    }
+}
+
+/// Format the color for SVG, e.g. `#ffffff80`.
+fn hex(color: &RGBA) -> String {
+    let channel = |value: f32| (value * 255.0).round() as u8;
+
+    format!(
+        "#{:02x}{:02x}{:02x}{:02x}",
+        channel(color.red()),
+        channel(color.green()),
+        channel(color.blue()),
+        channel(color.alpha())
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            Icon::parse("icon:battery-level-40-symbolic"),
+            Icon::Themed("battery-level-40-symbolic".to_string())
+        );
+        // Theme icons need the prefix, anything else is a glyph.
+        assert_eq!(
+            Icon::parse("battery-level-40-symbolic"),
+            Icon::Glyph("battery-level-40-symbolic".to_string())
+        );
+        assert_eq!(Icon::parse("x"), Icon::Glyph("x".to_string()));
+        assert!(matches!(Icon::parse("svg:battery"), Icon::Svg(_)));
+        assert!(Icon::parse("svg:missing").is_empty());
+    }
+
+    #[test]
+    fn overrides() {
+        let overrides = HashMap::from([("icon:battery".to_string(), "svg:battery".to_string())]);
+
+        let mut context = Context::new().with("icon", "icon:battery");
+        replace(&mut context, &overrides);
+        assert!(matches!(context.get("icon"), Some(Arg::Text(icon)) if icon == "svg:battery"));
+
+        let mut context = Context::new()
+            .with("icon", "x")
+            .with("other", "icon:battery");
+        replace(&mut context, &overrides);
+        assert!(matches!(context.get("icon"), Some(Arg::Text(icon)) if icon == "x"));
+        assert!(matches!(context.get("other"), Some(Arg::Text(icon)) if icon == "icon:battery"));
+    }
+
+    #[test]
+    fn battery_level() {
+        let Icon::Svg(empty) = battery(0.0) else {
+            panic!("battery is not an SVG");
+        };
+        let Icon::Svg(full) = battery(150.0) else {
+            panic!("battery is not an SVG");
+        };
+
+        assert!(empty.contains(r#"width="0.0""#));
+        assert!(full.contains(r#"width="22.0""#));
+    }
+}
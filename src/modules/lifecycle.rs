@@ -0,0 +1,178 @@
+//! Lifecycle of a module.
+//!
+//! A module is started by [Module::controllers](super::Module::controllers), can be
+//! paused and resumed, e.g. while its bar is hidden, and is stopped once its bar is
+//! closed. Tasks spawned with [Lifecycle::spawn] are cancelled when the module stops.
+//!
+//! A panicking task marks the lifecycle as failed, see [Lifecycle::failed].
+
+use std::{any::Any, future::Future, sync::Arc};
+
+use serde::Serialize;
+use tokio::sync::watch;
+
+use crate::rbar::RBar;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum State {
+    Running,
+    Paused,
+    Stopped,
+}
+
+/// [Lifecycle] is shared by a module and its tasks, like a cancellation token.
+#[derive(Debug, Clone)]
+pub struct Lifecycle {
+    tx: Arc<watch::Sender<State>>,
+    failure: Arc<watch::Sender<Option<String>>>,
+}
+
+impl Lifecycle {
+    pub fn new() -> Self {
+        let (tx, _) = watch::channel(State::Running);
+        let (failure, _) = watch::channel(None);
+
+        Self {
+            tx: Arc::new(tx),
+            failure: Arc::new(failure),
+        }
+    }
+
+    /// Create a lifecycle following this one, which can be stopped on its own.
+    pub fn child(&self) -> Self {
+        let child = Self::new();
+        child.set(self.state());
+
+        let mut rx = self.subscribe();
+        let lifecycle = child.clone();
+        RBar::runtime().spawn(async move {
+            loop {
+                tokio::select! {
+                    changed = rx.changed() => {
+                        let state = match changed {
+                            Ok(()) => *rx.borrow_and_update(),
+                            Err(_) => State::Stopped,
+                        };
+                        lifecycle.set(state);
+                    }
+                    _ = lifecycle.stopped() => break,
+                }
+            }
+        });
+
+        child
+    }
+
+    pub fn state(&self) -> State {
+        *self.tx.borrow()
+    }
+
+    /// Pause the module. Does nothing if it is stopped.
+    pub fn pause(&self) {
+        self.set(State::Paused);
+    }
+
+    /// Resume the module. Does nothing if it is stopped.
+    pub fn resume(&self) {
+        self.set(State::Running);
+    }
+
+    /// Stop the module and cancel its tasks.
+    pub fn stop(&self) {
+        self.set(State::Stopped);
+    }
+
+    fn set(&self, state: State) {
+        self.tx.send_if_modified(|current| {
+            let modified = *current != state && *current != State::Stopped;
+            if modified {
+                *current = state;
+            }
+            modified
+        });
+    }
+
+    /// Subscribe to changes of the state.
+    pub fn subscribe(&self) -> watch::Receiver<State> {
+        self.tx.subscribe()
+    }
+
+    /// Wait until the module is running. Never returns once it is stopped.
+    pub async fn running(&self) {
+        let mut rx = self.subscribe();
+        if rx.wait_for(|state| *state == State::Running).await.is_err() {
+            std::future::pending().await
+        }
+    }
+
+    /// Wait until the module is stopped.
+    pub async fn stopped(&self) {
+        let mut rx = self.subscribe();
+        // The sender lives as long as `self`.
+        let _ = rx.wait_for(|state| *state == State::Stopped).await;
+    }
+
+    /// Mark the module as failed, e.g. because its data source went away.
+    pub fn fail(&self, message: impl Into<String>) {
+        let message = message.into();
+        self.failure.send_if_modified(|failure| {
+            let modified = failure.is_none();
+            failure.get_or_insert(message);
+            modified
+        });
+    }
+
+    /// Wait until the module failed and get the reason.
+    pub async fn failed(&self) -> String {
+        let mut rx = self.failure.subscribe();
+        let failure = match rx.wait_for(Option::is_some).await {
+            Ok(failure) => failure.clone(),
+            // The sender lives as long as `self`.
+            Err(_) => None,
+        };
+        failure.unwrap_or_default()
+    }
+
+    /// Spawn a task on [RBar::runtime] which is cancelled when the module stops.
+    ///
+    /// If the task panics, the module is marked as failed.
+    pub fn spawn<F>(&self, future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let lifecycle = self.clone();
+        let runtime = RBar::runtime();
+        let task = runtime.spawn(future);
+
+        runtime.spawn(async move {
+            let abort = task.abort_handle();
+
+            tokio::select! {
+                result = task => match result {
+                    Err(e) if e.is_panic() => {
+                        lifecycle.fail(format!("Panicked: {}", panic_message(e.into_panic())));
+                    }
+                    _ => {}
+                },
+                _ = lifecycle.stopped() => abort.abort(),
+            }
+        });
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&'static str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "unknown reason".to_string(),
+        },
+    }
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
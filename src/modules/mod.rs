@@ -1,86 +1,316 @@
-use std::{fmt::Debug, sync::Arc};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    fmt::Debug,
+    rc::Rc,
+    sync::Arc,
+};
 
 use gtk::{glib, prelude::*, Widget};
-use serde::Deserialize;
-use tokio::sync::{broadcast, mpsc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::{mpsc, watch};
+use tracing::{debug, warn};
 
-use crate::{bar::Bar, RBar};
+use crate::{bar::Bar, plugins::Plugins, RBar};
 
+use self::{
+    display::{Display, Indicator},
+    format::{Context, Template},
+    lifecycle::Lifecycle,
+};
+
+mod actions;
 mod clock;
+mod condition;
+mod custom;
+mod display;
+mod format;
+mod group;
+mod icon;
+mod lifecycle;
+mod plugin;
+mod popover;
 mod power;
+mod states;
+mod supervisor;
+mod wasm;
 
 /// [WidgetContext] holds information about widget and rbar.
 #[derive(Debug)]
-pub struct WidgetContext<S: Clone> {
+pub struct WidgetContext<S: Clone, R> {
     /// Unique id for the widget. Generated by [RBar::unique_id].
     pub id: usize,
     /// A reference to the [rbar](RBar) instance.
     pub rbar: Arc<RBar>,
 
+    /// Send events from the controllers to the bar.
     pub tx: mpsc::Sender<Events<S>>,
-    pub update_tx: broadcast::Sender<S>,
+    /// Latest update of the module, see [WidgetContext::subscribe].
+    pub update_tx: watch::Sender<Option<S>>,
+    /// Send messages from the widget to the controllers.
+    pub controller_tx: mpsc::Sender<R>,
+    /// Lifecycle of the module. Spawn controller tasks with [Lifecycle::spawn].
+    pub lifecycle: Lifecycle,
 }
 
-impl<S: Clone> WidgetContext<S> {
+// Not derived, the messages of the controllers don't need to be `Clone`.
+impl<S: Clone, R> Clone for WidgetContext<S, R> {
+    fn clone(&self) -> Self {
+        Self {
+            id: self.id,
+            rbar: self.rbar.clone(),
+            tx: self.tx.clone(),
+            update_tx: self.update_tx.clone(),
+            controller_tx: self.controller_tx.clone(),
+            lifecycle: self.lifecycle.clone(),
+        }
+    }
+}
+
+impl<S: Clone, R> WidgetContext<S, R> {
     /// Subscribe to the update channel of the module to receive updates and handle them.
-    pub fn subscribe(&self) -> broadcast::Receiver<S> {
-        self.update_tx.subscribe()
+    pub fn subscribe(&self) -> Updates<S> {
+        let mut rx = self.update_tx.subscribe();
+        // Start with the latest update, if there is one.
+        rx.mark_changed();
+
+        Updates { rx }
+    }
+}
+
+/// [Updates] receives the updates of a module.
+///
+/// Only the latest update is kept, so a receiver that falls behind skips
+/// intermediate updates instead of missing new ones.
+pub struct Updates<S> {
+    rx: watch::Receiver<Option<S>>,
+}
+
+impl<S: Clone> Updates<S> {
+    /// Wait for the next update. Returns `None` once the module is gone.
+    pub async fn recv(&mut self) -> Option<S> {
+        loop {
+            self.rx.changed().await.ok()?;
+            if let Some(data) = self.rx.borrow_and_update().clone() {
+                return Some(data);
+            }
+        }
     }
 }
 
 pub trait Module<W: IsA<Widget>> {
-    /// Data to be received from the module.
-    type Receive;
+    /// Data to be received by the controllers from the widget.
+    type Receive: Send + 'static;
     /// Data to be sent from the module.
-    type Send: Clone + Debug + Send + 'static;
+    type Send: Clone + Debug + Serialize + Send + 'static;
 
     /// Name of the module.
     /// Can be used to identify the module and for styling purposes.
     fn name() -> &'static str;
 
-    /// Create controllers to handle certain events.
-    fn controllers(&self, context: &WidgetContext<Self::Send>) -> crate::Result<()> {
+    /// Name of this instance of the module, used for its widget, events and
+    /// errors. Same as [Module::name], except for modules provided by plugins.
+    fn module_name(&self) -> &'static str {
+        Self::name()
+    }
+
+    /// Create controllers to handle certain events. Starts the module.
+    ///
+    /// Tasks must be spawned with [Lifecycle::spawn] so they are cancelled once
+    /// the module stops. `rx` receives the messages the widget sends with [WidgetContext::controller_tx].
+    fn controllers(
+        &self,
+        _context: &WidgetContext<Self::Send, Self::Receive>,
+        _rx: mpsc::Receiver<Self::Receive>,
+    ) -> crate::Result<()> {
         Ok(())
     }
 
     /// Create the widget. Return the widget itself.
-    fn widget(&self, context: WidgetContext<Self::Send>) -> crate::Result<W>;
+    fn widget(&self, context: WidgetContext<Self::Send, Self::Receive>) -> crate::Result<W>;
+
+    /// Create a detail panel shown in a popover below the module when it is clicked.
+    fn popover(
+        &self,
+        _context: &WidgetContext<Self::Send, Self::Receive>,
+    ) -> crate::Result<Option<Widget>> {
+        Ok(None)
+    }
+
+    /// Run a module-defined action, e.g. requested with `rbar msg module <id> action <name>`.
+    fn action(
+        &self,
+        name: &str,
+        _context: &WidgetContext<Self::Send, Self::Receive>,
+    ) -> crate::Result<()> {
+        Err(format!("Module '{}' has no action '{}'", self.module_name(), name).into())
+    }
+
+    /// Called when the module is paused, e.g. because its bar got hidden.
+    fn pause(&self, _context: &WidgetContext<Self::Send, Self::Receive>) {}
+
+    /// Called when the module is resumed after being paused.
+    fn resume(&self, _context: &WidgetContext<Self::Send, Self::Receive>) {}
+
+    /// Called when the module is stopped, after its tasks got cancelled.
+    fn stop(&self, _context: &WidgetContext<Self::Send, Self::Receive>) {}
 
     /// Get module configuration.
     fn get_base_config(&self) -> &BaseModuleConfig;
 
+    /// Values available to the templates of the module, like the `tooltip`.
+    fn format_context(&self, _data: &Self::Send) -> Context {
+        Context::new()
+    }
+
+    /// Values available to all templates: those of [Module::format_context], with
+    /// the `{icon}` replaced as configured in `icons`, and the `{level}` of the
+    /// module, see [Module::level].
+    fn template_context(&self, data: &Self::Send) -> Context {
+        let mut context = self.format_context(data);
+        icon::replace(&mut context, &self.get_base_config().icons);
+        context.set("level", self.level(data).unwrap_or_default());
+        context
+    }
+
+    /// Numeric value of an update, matched against the `states` of the module
+    /// and shown in percent by its `display`, see [display].
+    fn value(&self, _data: &Self::Send) -> Option<f64> {
+        None
+    }
+
+    /// Whether states are active above or below their threshold, unless configured.
+    fn state_direction(&self) -> states::Direction {
+        states::Direction::Above
+    }
+
+    /// Get the states of the module, the configured `states` by default.
+    fn states(&self) -> Option<&states::States> {
+        self.get_base_config().states.as_ref()
+    }
+
+    /// Get the active state for an update, e.g. `critical`.
+    fn level(&self, data: &Self::Send) -> Option<&str> {
+        self.states()?
+            .get(self.value(data)?, self.state_direction())
+    }
+
+    /// Css classes of the widget for an update, e.g. `charging`, replaced by
+    /// those of the next update. The active state is added on top.
+    fn classes(&self, _data: &Self::Send) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Tooltip markup used if no `tooltip` template is configured.
+    fn tooltip(&self, _data: &Self::Send) -> Option<String> {
+        None
+    }
+
+    /// Check if the module should be shown for the given update.
+    ///
+    /// Combined with the `visible_when` condition of the module configuration.
+    fn is_visible(&self, _data: &Self::Send) -> bool {
+        true
+    }
+
     /// Check if the module is enabled.
     fn is_enabled(&self) -> bool {
         self.get_base_config().enabled
     }
 
-    fn get_position(&self) -> &ModulePosition {
-        &self.get_base_config().position
+    fn get_position(&self) -> Option<ModulePosition> {
+        self.get_base_config().position
     }
 }
 
 /// [ModuleFactory] is creating instances of modules.
 pub struct ModuleFactory {
     rbar: Arc<RBar>,
+    /// Container for all modules, instead of the one of their position.
+    container: Option<gtk::Box>,
+    /// Position of all modules, e.g. that of their group.
+    position: Option<ModulePosition>,
 }
 
 impl ModuleFactory {
     /// Create a new [ModuleFactory].
     pub fn new(rbar: Arc<RBar>) -> Self {
-        Self { rbar }
+        Self {
+            rbar,
+            container: None,
+            position: None,
+        }
+    }
+
+    /// Create a [ModuleFactory] adding all modules to the container at the
+    /// given position, e.g. of a group.
+    pub fn with_container(&self, container: gtk::Box, position: ModulePosition) -> Self {
+        Self {
+            rbar: self.rbar.clone(),
+            container: Some(container),
+            position: Some(position),
+        }
+    }
+
+    /// Get the position of a module and the container to add it to.
+    fn container<'a>(
+        &'a self,
+        config: &BaseModuleConfig,
+        bar: &'a Bar,
+    ) -> crate::Result<(ModulePosition, &'a gtk::Box)> {
+        let position = self
+            .position
+            .or(config.position)
+            .ok_or("Module has no 'position'")?;
+
+        let container = match (&self.container, position) {
+            (Some(container), _) => container,
+            (None, ModulePosition::Left) => &bar.left,
+            (None, ModulePosition::Center) => &bar.center,
+            (None, ModulePosition::Right) => &bar.right,
+        };
+
+        Ok((position, container))
     }
 
     /// Create a widget and adds it to the container.
-    fn create<M, W>(&self, module: &M, bar: &Bar) -> crate::Result<()>
+    ///
+    /// Returns `None` if the module is disabled. If the module fails to load,
+    /// its error state is shown instead.
+    fn create<M, W>(&self, module: &M, bar: &Bar) -> crate::Result<Option<ModuleHandle>>
+    where
+        M: Module<W> + Clone + 'static,
+        W: IsA<Widget>,
+    {
+        self.try_create(module, bar).inspect_err(|e| {
+            self.create_error(
+                module.module_name(),
+                module.get_base_config(),
+                bar,
+                &e.to_string(),
+            );
+        })
+    }
+
+    fn try_create<M, W>(&self, module: &M, bar: &Bar) -> crate::Result<Option<ModuleHandle>>
     where
-        M: Module<W>,
+        M: Module<W> + Clone + 'static,
         W: IsA<Widget>,
     {
+        if !module.is_enabled() {
+            debug!("Skipping disabled module '{}'", module.module_name());
+            return Ok(None);
+        }
+
+        let (position, container) = self.container(module.get_base_config(), bar)?;
+
         let id = RBar::unique_id();
 
         let (ui_tx, ui_rx) = mpsc::channel::<Events<M::Send>>(32);
 
-        let (tx, _) = broadcast::channel(32);
+        let (tx, _) = watch::channel(None);
+        let (controller_tx, controller_rx) = mpsc::channel::<M::Receive>(16);
 
         let context = WidgetContext {
             id,
@@ -88,70 +318,519 @@ impl ModuleFactory {
 
             tx: ui_tx,
             update_tx: tx.clone(),
+            controller_tx,
+            lifecycle: Lifecycle::new(),
         };
 
-        // Create controllers.
-        module.controllers(&context)?;
-
-        // Get container.
-        let container = match module.get_position() {
-            ModulePosition::Left => &bar.left,
-            ModulePosition::Center => &bar.center,
-            ModulePosition::Right => &bar.right,
+        // Create the widget and the detail panel first, nothing is started or
+        // shown yet if one of them fails.
+        let created = module
+            .widget(context.clone())
+            .and_then(|widget| Ok((widget, module.popover(&context)?)));
+        let (widget, popover) = created.inspect_err(|_| {
+            context.lifecycle.stop();
+            module.stop(&context);
+        })?;
+
+        // Show the value of the module next to its widget, if configured.
+        let indicator = Indicator::new(module.get_base_config().display);
+        let widget: Widget = match &indicator {
+            Some(indicator) => {
+                let container = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+                container.append(&widget);
+                container.append(&indicator.widget());
+                container.upcast()
+            }
+            None => widget.upcast(),
         };
-
-        // Create widget.
-        let widget = module.widget(context)?;
-        widget.set_widget_name(M::name());
-        widget.add_css_class("widget");
-        widget.add_css_class(M::name());
+        style(&widget, module.module_name(), module.get_base_config());
 
         // Append widget to container.
         container.append(&widget);
 
+        // Show or hide the widget and update its tooltip, state and indicator
+        // depending on its updates.
+        let requested = Rc::new(Cell::new(true));
+        let update_visibility = self.visibility(module, &widget, bar, requested.clone());
+        update_visibility(None, &Value::Null);
+        let update_widget = update::<M, W>(module, &widget, indicator);
+
+        let receiver = Receiver {
+            id,
+            name: module.module_name(),
+            widget: widget.clone(),
+            state: Rc::new(RefCell::new(Value::Null)),
+            requested,
+        };
+        let state = receiver.state.clone();
+
         // Setup receiver for module updates (and other events).
-        self.setup_receiver(tx, ui_rx);
+        receiver.run(
+            tx,
+            ui_rx,
+            context.lifecycle.clone(),
+            update_visibility,
+            update_widget,
+        );
+
+        self.lifecycle_hooks(module, &context);
+        widget.connect_destroy({
+            let lifecycle = context.lifecycle.clone();
+            move |_| lifecycle.stop()
+        });
 
-        Ok(())
+        let action: ActionFn = {
+            let module = module.clone();
+            let context = context.clone();
+            Rc::new(move |name| module.action(name, &context))
+        };
+
+        // Run configured actions on clicks, scrolling and hovering.
+        let config = module.get_base_config();
+        actions::setup(
+            &widget,
+            &config.actions,
+            id,
+            module.module_name(),
+            action.clone(),
+        );
+
+        // Show the detail panel on left click, unless the click has an action.
+        if let Some(content) = popover {
+            let open_on_click = !config.actions.contains_key(&actions::Gesture::LeftClick);
+            popover::setup(&widget, &content, module.module_name(), open_on_click);
+        }
+
+        // Create controllers, restarting them when they fail.
+        supervisor::supervise(module, &context, controller_rx);
+
+        Ok(Some(ModuleHandle {
+            id,
+            config_id: config.id.clone(),
+            name: module.module_name(),
+            output: bar.output().to_string(),
+            position,
+            state,
+            action,
+            lifecycle: context.lifecycle,
+        }))
     }
 
-    fn setup_receiver<S: Clone + Debug + Send + 'static>(
+    /// Run the lifecycle hooks of the module when its state changes.
+    fn lifecycle_hooks<M, W>(&self, module: &M, context: &WidgetContext<M::Send, M::Receive>)
+    where
+        M: Module<W> + Clone + 'static,
+        W: IsA<Widget>,
+    {
+        let module = module.clone();
+        let context = context.clone();
+        let mut rx = context.lifecycle.subscribe();
+
+        glib::spawn_future_local(async move {
+            while rx.changed().await.is_ok() {
+                let state = *rx.borrow_and_update();
+                match state {
+                    lifecycle::State::Running => module.resume(&context),
+                    lifecycle::State::Paused => module.pause(&context),
+                    lifecycle::State::Stopped => {
+                        module.stop(&context);
+                        break;
+                    }
+                }
+            }
+        });
+    }
+
+    /// Show the error state of a module that failed to load, in place of its widget.
+    fn create_error(&self, name: &str, config: &BaseModuleConfig, bar: &Bar, message: &str) {
+        let Ok((_, container)) = self.container(config, bar) else {
+            return;
+        };
+
+        let widget = gtk::Label::new(Some("⚠"));
+        style(&widget, name, config);
+        widget.add_css_class("error");
+        widget.set_tooltip_text(Some(message));
+
+        container.append(&widget);
+    }
+
+    /// Create a function deciding whether the widget is visible for an update.
+    ///
+    /// The `visible_when` condition is evaluated against the serialized update,
+    /// together with `output` and `battery.present`, which is checked once when the module is created.
+    /// The widget is always hidden while the module `requested` to hide it with [Events::Visibility].
+    fn visibility<M, W>(
         &self,
-        tx: broadcast::Sender<S>,
+        module: &M,
+        widget: &Widget,
+        bar: &Bar,
+        requested: Rc<Cell<bool>>,
+    ) -> impl Fn(Option<&M::Send>, &Value) + 'static
+    where
+        M: Module<W> + Clone + 'static,
+        W: IsA<Widget>,
+    {
+        let module = module.clone();
+        let widget = widget.clone();
+        let condition = module.get_base_config().visible_when.clone();
+
+        let output = bar.output().to_string();
+        // Only look for batteries when needed.
+        let battery = condition
+            .as_ref()
+            .is_some_and(|condition| condition.uses("battery"))
+            .then(|| json!({ "present": has_battery() }));
+
+        move |data, state| {
+            let visible_data = data.is_none_or(|data| module.is_visible(data));
+            let visible_condition = condition.as_ref().is_none_or(|condition| {
+                let mut context = match state {
+                    Value::Object(state) => state.clone(),
+                    _ => Default::default(),
+                };
+                context.insert("output".to_string(), json!(output));
+                if let Some(battery) = &battery {
+                    context.insert("battery".to_string(), battery.clone());
+                }
+
+                condition.evaluate(&Value::Object(context))
+            });
+
+            widget.set_visible(requested.get() && visible_data && visible_condition);
+        }
+    }
+}
+
+/// [Receiver] handles the [Events] sent by the controllers of a module.
+struct Receiver {
+    id: usize,
+    name: &'static str,
+    widget: Widget,
+    /// Latest update, serialized.
+    state: Rc<RefCell<Value>>,
+    /// Visibility requested by the module.
+    requested: Rc<Cell<bool>>,
+}
+
+impl Receiver {
+    fn run<S: Clone + Debug + Serialize + Send + 'static>(
+        self,
+        tx: watch::Sender<Option<S>>,
         mut rx: mpsc::Receiver<Events<S>>,
+        lifecycle: Lifecycle,
+        update_visibility: impl Fn(Option<&S>, &Value) + 'static,
+        update_widget: impl Fn(&S) + 'static,
     ) {
         glib::spawn_future_local(async move {
-            while let Some(event) = rx.recv().await {
+            let mut last = None;
+
+            loop {
+                let event = tokio::select! {
+                    event = rx.recv() => event,
+                    _ = lifecycle.stopped() => None,
+                };
+                let Some(event) = event else {
+                    break;
+                };
+
                 use Events::*;
-                match event {
+                let kind = match event {
                     Update(data) => {
-                        // todo: handle error
-                        tx.send(data).expect("Handle error!!!");
+                        let value = serde_json::to_value(&data).unwrap_or_default();
+                        self.widget.remove_css_class("error");
+                        update_visibility(Some(&data), &value);
+                        update_widget(&data);
+                        *self.state.borrow_mut() = value.clone();
+
+                        // Widgets that are not listening get the latest update once they do.
+                        tx.send_replace(Some(data.clone()));
+                        last = Some(data);
+
+                        ModuleEventKind::Update { data: value }
                     }
-                }
+                    Error(message) => {
+                        warn!("Module '{}' ({}) failed: {}", self.name, self.id, message);
+                        self.widget.add_css_class("error");
+                        self.widget.set_tooltip_text(Some(&message));
+
+                        ModuleEventKind::Error { message }
+                    }
+                    Visibility(visible) => {
+                        self.requested.set(visible);
+                        update_visibility(last.as_ref(), &self.state.borrow());
+
+                        ModuleEventKind::Visibility { visible }
+                    }
+                    Attention(active) => {
+                        if active {
+                            self.widget.add_css_class("attention");
+                        } else {
+                            self.widget.remove_css_class("attention");
+                        }
+
+                        ModuleEventKind::Attention { active }
+                    }
+                };
+
+                // Nobody might be listening.
+                let _ = RBar::events().send(ModuleEvent {
+                    id: self.id,
+                    name: self.name,
+                    kind,
+                });
             }
         });
     }
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(tag = "name", rename_all = "snake_case")]
+type ActionFn = Rc<dyn Fn(&str) -> crate::Result<()>>;
+
+/// [ModuleHandle] refers to a module that has been added to a bar.
+pub struct ModuleHandle {
+    /// Unique id of the module. Same as [WidgetContext::id].
+    pub id: usize,
+    /// The `id` of the module configuration, if set.
+    pub config_id: Option<String>,
+    pub name: &'static str,
+    /// Output of the bar the module lives on.
+    pub output: String,
+    pub position: ModulePosition,
+
+    state: Rc<RefCell<Value>>,
+    action: ActionFn,
+    lifecycle: Lifecycle,
+}
+
+impl ModuleHandle {
+    /// Get serializable information about the module.
+    pub fn info(&self) -> ModuleInfo {
+        ModuleInfo {
+            id: self.id,
+            config_id: self.config_id.clone(),
+            name: self.name,
+            output: self.output.clone(),
+            position: self.position,
+            state: self.lifecycle.state(),
+        }
+    }
+
+    /// Pause the module, e.g. while its bar is hidden.
+    pub fn pause(&self) {
+        self.lifecycle.pause();
+    }
+
+    /// Resume the module after it got paused.
+    pub fn resume(&self) {
+        self.lifecycle.resume();
+    }
+
+    /// Stop the module and cancel its tasks.
+    pub fn stop(&self) {
+        self.lifecycle.stop();
+    }
+
+    /// Get the latest update sent by the module.
+    pub fn state(&self) -> Value {
+        self.state.borrow().clone()
+    }
+
+    /// Run a module-defined action.
+    pub fn action(&self, name: &str) -> crate::Result<()> {
+        (self.action)(name)
+    }
+}
+
+impl Debug for ModuleHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ModuleHandle")
+            .field("id", &self.id)
+            .field("config_id", &self.config_id)
+            .field("name", &self.name)
+            .field("output", &self.output)
+            .field("position", &self.position)
+            .finish_non_exhaustive()
+    }
+}
+
+/// [ModuleEvent] is published on [RBar::events] for every module update.
+#[derive(Debug, Clone, Serialize)]
+pub struct ModuleEvent {
+    /// Unique id of the module.
+    pub id: usize,
+    pub name: &'static str,
+    #[serde(flatten)]
+    pub kind: ModuleEventKind,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ModuleEventKind {
+    /// The module sent an update, `data` is the serialized update.
+    Update { data: Value },
+    /// An `ipc` action configured on the module was triggered.
+    Action { event: String },
+    /// The module failed, e.g. a command could not be run.
+    Error { message: String },
+    /// The module requested to be shown or hidden.
+    Visibility { visible: bool },
+    /// The module requested attention or stopped requesting it.
+    Attention { active: bool },
+}
+
+/// Information about a loaded module, as listed over IPC.
+#[derive(Debug, Serialize)]
+pub struct ModuleInfo {
+    pub id: usize,
+    /// The `id` of the module configuration, also accepted in place of the `id`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config_id: Option<String>,
+    pub name: &'static str,
+    pub output: String,
+    pub position: ModulePosition,
+    pub state: lifecycle::State,
+}
+
+#[derive(Debug, Clone)]
 pub enum Modules {
     Clock(clock::Clock),
+    Custom(custom::Custom),
+    Group(group::Group),
     Power(power::Power),
+    Wasm(wasm::Wasm),
+    /// Any other name refers to a plugin.
+    Plugin(plugin::PluginModule),
+}
+
+impl<'de> Deserialize<'de> for Modules {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(tag = "name", rename_all = "snake_case")]
+        enum Builtin {
+            Clock(clock::Clock),
+            Custom(custom::Custom),
+            Group(group::Group),
+            Power(power::Power),
+            Wasm(wasm::Wasm),
+        }
+
+        let value = Value::deserialize(deserializer)?;
+        let name = value
+            .get("name")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+
+        if !Modules::BUILTIN.contains(&name) {
+            return plugin::PluginModule::deserialize(value)
+                .map(Self::Plugin)
+                .map_err(serde::de::Error::custom);
+        }
+
+        Ok(
+            match Builtin::deserialize(value).map_err(serde::de::Error::custom)? {
+                Builtin::Clock(module) => Self::Clock(module),
+                Builtin::Custom(module) => Self::Custom(module),
+                Builtin::Group(module) => Self::Group(module),
+                Builtin::Power(module) => Self::Power(module),
+                Builtin::Wasm(module) => Self::Wasm(module),
+            },
+        )
+    }
 }
 
 impl Modules {
-    pub fn create(&self, module_factory: &ModuleFactory, bar: &Bar) -> crate::Result<()> {
+    /// Names of the modules built into rbar. Any other name refers to a plugin.
+    const BUILTIN: &'static [&'static str] = &["clock", "custom", "group", "power", "wasm"];
+
+    /// Check the configuration of a module on the bar, and of the modules of a group.
+    ///
+    /// Fails for modules without a position, for names that are neither builtin
+    /// modules nor loaded plugins, and for options that can't be used together.
+    pub fn validate(&self, plugins: &Plugins) -> crate::Result<()> {
+        if self.base_config().position.is_none() {
+            return Err(format!("Module '{}' has no 'position'", self.name()).into());
+        }
+
+        self.validate_options(plugins)
+    }
+
+    /// Check the configuration of a module, which doesn't need a position in a group.
+    fn validate_options(&self, plugins: &Plugins) -> crate::Result<()> {
+        match self {
+            Self::Plugin(module) if plugins.get(module.name).is_none() => {
+                let available: Vec<&str> = Self::BUILTIN
+                    .iter()
+                    .copied()
+                    .chain(plugins.names())
+                    .collect();
+                Err(format!(
+                    "Unknown module '{}', available modules: {}",
+                    module.name,
+                    available.join(", ")
+                )
+                .into())
+            }
+            Self::Custom(module) => module.validate(),
+            Self::Group(group) => group
+                .modules
+                .iter()
+                .try_for_each(|module| module.validate_options(plugins)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Get the name of the module, e.g. `clock` or the name of a plugin.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Clock(module) => module.module_name(),
+            Self::Custom(module) => module.module_name(),
+            Self::Group(_) => group::Group::NAME,
+            Self::Power(module) => module.module_name(),
+            Self::Wasm(module) => module.module_name(),
+            Self::Plugin(module) => module.module_name(),
+        }
+    }
+
+    /// Get the configuration shared by all modules.
+    pub fn base_config(&self) -> &BaseModuleConfig {
+        match self {
+            Self::Clock(module) => module.get_base_config(),
+            Self::Custom(module) => module.get_base_config(),
+            Self::Group(module) => module.get_base_config(),
+            Self::Power(module) => module.get_base_config(),
+            Self::Wasm(module) => module.get_base_config(),
+            Self::Plugin(module) => module.get_base_config(),
+        }
+    }
+
+    /// Sort modules by their `order`, keeping the order of the config otherwise.
+    pub fn sorted(modules: &[Self]) -> Vec<&Self> {
+        let mut modules: Vec<&Self> = modules.iter().collect();
+        modules.sort_by_key(|module| module.base_config().order);
+        modules
+    }
+
+    /// Create the module. Groups return the handles of their modules as well.
+    pub fn create(
+        &self,
+        module_factory: &ModuleFactory,
+        bar: &Bar,
+    ) -> crate::Result<Vec<ModuleHandle>> {
         macro_rules! create {
             ($module:expr) => {
-                module_factory.create($module, bar)
+                module_factory
+                    .create($module, bar)
+                    .map(|handle| handle.into_iter().collect())
             };
         }
 
         match self {
             Self::Clock(module) => create!(module),
+            Self::Custom(module) => create!(module),
+            Self::Group(group) => group.create(module_factory, bar),
             Self::Power(module) => create!(module),
+            Self::Wasm(module) => create!(module),
+            Self::Plugin(module) => create!(module),
         }
     }
 }
@@ -160,19 +839,180 @@ impl Modules {
 pub enum Events<S: Clone> {
     /// Modules updates.
     Update(S),
+    /// The module failed. Adds the `error` class to the widget and shows the
+    /// message as its tooltip until the next update.
+    Error(String),
+    /// Show or hide the widget, independent of `visible_when`.
+    Visibility(bool),
+    /// Request attention, e.g. for a critical battery.
+    /// Toggles the `attention` class of the widget.
+    Attention(bool),
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct BaseModuleConfig {
+    #[serde(default = "enabled_default")]
     pub enabled: bool,
-    pub position: ModulePosition,
+
+    /// Where the module is shown on the bar. Required, except for the modules
+    /// of a group, which are shown at the position of their group.
+    #[serde(default)]
+    pub position: Option<ModulePosition>,
+
+    /// Name of the widget, to style a single instance of a module with `#<id>`.
+    /// Defaults to the name of the module.
+    #[serde(default)]
+    pub id: Option<String>,
+
+    /// Css classes added to the widget, either a single class or a list of classes.
+    #[serde(default, deserialize_with = "string_or_list")]
+    pub classes: Vec<String>,
+
+    /// Modules are sorted by their order within their container, lowest first.
+    /// Modules with the same order keep the order of the config.
+    #[serde(default)]
+    pub order: i32,
+
+    /// Named thresholds of the value of the module, added as classes to the widget.
+    ///
+    /// Example: `{"warning": 30, "critical": 15}`
+    #[serde(default)]
+    pub states: Option<states::States>,
+
+    /// Show the value of the module next to it as a `bar`, `graph` or `circle`.
+    /// Only used by modules with a value, see [Module::value].
+    #[serde(default)]
+    pub display: Display,
+
+    /// Replace the `{icon}` of the module by its text, see [icon].
+    ///
+    /// Example: `{"icon:battery-level-40-symbolic": "svg:battery"}`
+    #[serde(default)]
+    pub icons: HashMap<String, String>,
+
+    /// Tooltip of the module. Values depend on the module.
+    ///
+    /// Example: `{time:%A, %d %B %Y}` for the clock
+    #[serde(default)]
+    pub tooltip: Option<Template>,
+
+    /// Only show the module while the condition holds.
+    ///
+    /// Example: `state == "discharging"` or `output == "eDP-1"`
+    #[serde(default)]
+    pub visible_when: Option<condition::Condition>,
+
+    /// Actions to run on clicks, scrolling and hovering.
+    #[serde(default)]
+    pub actions: HashMap<actions::Gesture, actions::Action>,
 }
 
 /// [ModulePosition] is used to get the container wher the module should be added.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum ModulePosition {
     Left,
     Center,
     Right,
 }
+
+/// Set the name and classes of the widget of a module.
+fn style(widget: &impl IsA<Widget>, name: &str, config: &BaseModuleConfig) {
+    widget.set_widget_name(config.id.as_deref().unwrap_or(name));
+    widget.add_css_class("widget");
+    widget.add_css_class(name);
+    for class in config.classes.iter() {
+        widget.add_css_class(class);
+    }
+}
+
+/// Create a function updating the widget for an update: its tooltip, its
+/// classes and its indicator.
+fn update<M, W>(module: &M, widget: &Widget, indicator: Option<Indicator>) -> impl Fn(&M::Send)
+where
+    M: Module<W> + Clone + 'static,
+    W: IsA<Widget>,
+{
+    let update_tooltip = tooltip::<M, W>(module, widget);
+    let update_classes = classes::<M, W>(module, widget);
+    let module = module.clone();
+
+    move |data| {
+        update_tooltip(data);
+        update_classes(data);
+        if let (Some(indicator), Some(value)) = (&indicator, module.value(data)) {
+            indicator.set(value);
+        }
+    }
+}
+
+/// Create a function updating the tooltip of the widget for an update.
+fn tooltip<M, W>(module: &M, widget: &Widget) -> impl Fn(&M::Send) + 'static
+where
+    M: Module<W> + Clone + 'static,
+    W: IsA<Widget>,
+{
+    let module = module.clone();
+    let widget = widget.clone();
+
+    move |data| {
+        let markup = match &module.get_base_config().tooltip {
+            Some(template) => Some(template.render(&module.template_context(data))),
+            None => module.tooltip(data),
+        };
+
+        widget.set_tooltip_markup(markup.as_deref().filter(|markup| !markup.is_empty()));
+    }
+}
+
+/// Create a function replacing the classes of the widget with those of an update,
+/// see [Module::classes] and [Module::level].
+fn classes<M, W>(module: &M, widget: &Widget) -> impl Fn(&M::Send) + 'static
+where
+    M: Module<W> + Clone + 'static,
+    W: IsA<Widget>,
+{
+    let module = module.clone();
+    let widget = widget.clone();
+    let applied = RefCell::new(Vec::<String>::new());
+
+    move |data| {
+        let mut classes = module.classes(data);
+        classes.extend(module.level(data).map(str::to_string));
+
+        let mut applied = applied.borrow_mut();
+        for class in applied.iter().filter(|class| !classes.contains(class)) {
+            widget.remove_css_class(class);
+        }
+        for class in classes.iter() {
+            widget.add_css_class(class);
+        }
+        *applied = classes;
+    }
+}
+
+fn enabled_default() -> bool {
+    true
+}
+
+/// Deserialize either a single string or a list of strings, e.g. css classes.
+pub fn string_or_list<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StringOrList {
+        String(String),
+        List(Vec<String>),
+    }
+
+    Ok(match StringOrList::deserialize(deserializer)? {
+        StringOrList::String(string) => vec![string],
+        StringOrList::List(list) => list,
+    })
+}
+
+fn has_battery() -> bool {
+    battery::Manager::new().has_batteries()
+}
@@ -0,0 +1,180 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeSet, HashMap},
+    ffi::{c_char, c_void, CStr},
+    sync::Mutex,
+};
+
+use gtk::{glib::translate::FromGlibPtrFull, Widget};
+use rbar_plugin::Host;
+use serde::{Deserialize, Deserializer};
+use serde_json::{Map, Value};
+use tokio::sync::mpsc;
+use tracing::debug;
+
+use crate::plugins::Instance;
+
+use super::{format::Context, BaseModuleConfig, Events, Module, WidgetContext};
+
+thread_local! {
+    /// Modules created by plugins by module id. They live on the GTK main thread.
+    static INSTANCES: RefCell<HashMap<usize, (Instance, *mut HostData)>> = RefCell::default();
+}
+
+/// A module provided by a plugin, see [crate::plugins].
+#[derive(Debug, Clone, Deserialize)]
+pub struct PluginModule {
+    /// Name of the plugin.
+    #[serde(deserialize_with = "intern")]
+    pub name: &'static str,
+    config: BaseModuleConfig,
+
+    /// All other options are passed to the plugin.
+    #[serde(flatten)]
+    options: Map<String, Value>,
+}
+
+impl Module<Widget> for PluginModule {
+    type Receive = ();
+    type Send = Value;
+
+    fn name() -> &'static str {
+        "plugin"
+    }
+
+    fn module_name(&self) -> &'static str {
+        self.name
+    }
+
+    fn widget(&self, context: WidgetContext<Self::Send, Self::Receive>) -> crate::Result<Widget> {
+        let plugin = context
+            .rbar
+            .plugins
+            .get(self.name)
+            .ok_or_else(|| format!("No plugin named '{}'", self.name))?;
+
+        let data = Box::into_raw(Box::new(HostData {
+            tx: context.tx.clone(),
+        }));
+        let host = Host {
+            data: data.cast(),
+            update: host_update,
+            error: host_error,
+        };
+
+        let instance = match plugin.create(&self.options, host) {
+            Ok(instance) => instance,
+            Err(e) => {
+                drop(unsafe { Box::from_raw(data) });
+                return Err(format!("Failed to create '{}': {}", self.name, e).into());
+            }
+        };
+
+        let widget = instance.widget();
+        if widget.is_null() {
+            drop(instance);
+            drop(unsafe { Box::from_raw(data) });
+            return Err(format!("Plugin '{}' returned no widget", self.name).into());
+        }
+        INSTANCES.with_borrow_mut(|instances| instances.insert(context.id, (instance, data)));
+
+        // Safety: plugins return a new reference to a `GtkWidget`.
+        let widget: Widget = unsafe { Widget::from_glib_full(widget.cast()) };
+
+        Ok(widget)
+    }
+
+    /// Available: all top-level values of the state published by the plugin.
+    fn format_context(&self, state: &Self::Send) -> Context {
+        let mut context = Context::new();
+
+        for (name, value) in state.as_object().into_iter().flatten() {
+            match value {
+                Value::String(text) => context.set(name, text.as_str()),
+                Value::Number(number) => context.set(name, number.as_f64().unwrap_or_default()),
+                Value::Bool(value) => context.set(name, *value),
+                _ => {}
+            }
+        }
+
+        context
+    }
+
+    fn action(
+        &self,
+        name: &str,
+        context: &WidgetContext<Self::Send, Self::Receive>,
+    ) -> crate::Result<()> {
+        INSTANCES.with_borrow(|instances| match instances.get(&context.id) {
+            Some((instance, _)) => instance.action(name),
+            None => Err(format!("Plugin '{}' is not running", self.name).into()),
+        })
+    }
+
+    fn stop(&self, context: &WidgetContext<Self::Send, Self::Receive>) {
+        let Some((instance, data)) =
+            INSTANCES.with_borrow_mut(|instances| instances.remove(&context.id))
+        else {
+            return;
+        };
+
+        debug!("Destroying plugin module '{}'", self.name);
+        // The plugin can't use the host anymore once destroyed.
+        drop(instance);
+        drop(unsafe { Box::from_raw(data) });
+    }
+
+    fn get_base_config(&self) -> &BaseModuleConfig {
+        &self.config
+    }
+}
+
+/// Get the name as a static string, like the names of builtin modules.
+///
+/// Names are only leaked once, no matter how often the config is loaded.
+fn intern<'de, D>(deserializer: D) -> Result<&'static str, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    static NAMES: Mutex<BTreeSet<&'static str>> = Mutex::new(BTreeSet::new());
+
+    let name = String::deserialize(deserializer)?;
+    let mut names = NAMES.lock().unwrap();
+    if let Some(name) = names.get(name.as_str()) {
+        return Ok(name);
+    }
+
+    let name = Box::leak(name.into_boxed_str());
+    names.insert(name);
+    Ok(name)
+}
+
+/// Data behind [Host::data].
+struct HostData {
+    tx: mpsc::Sender<Events<Value>>,
+}
+
+unsafe extern "C" fn host_update(data: *mut c_void, state: *const c_char) {
+    let data = &*data.cast::<HostData>();
+    let state = CStr::from_ptr(state).to_string_lossy();
+
+    let event = match serde_json::from_str(&state) {
+        Ok(state) => Events::Update(state),
+        Err(e) => Events::Error(format!("Invalid state: {}", e)),
+    };
+    send(data, event);
+}
+
+unsafe extern "C" fn host_error(data: *mut c_void, message: *const c_char) {
+    let data = &*data.cast::<HostData>();
+    let message = CStr::from_ptr(message).to_string_lossy();
+
+    send(data, Events::Error(message.into_owned()));
+}
+
+/// Send without blocking, plugins may call the host from the GTK main thread.
+fn send(data: &HostData, event: Events<Value>) {
+    if data.tx.try_send(event).is_err() {
+        debug!("Dropping event of plugin module");
+    }
+}
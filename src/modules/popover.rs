@@ -0,0 +1,62 @@
+use gtk::{prelude::*, GestureClick, Popover, PositionType, PropagationPhase, Widget};
+use gtk4_layer_shell::{KeyboardMode, LayerShell};
+
+/// Attach a popover with the given content to the widget.
+///
+/// The popover opens below the widget on left click if `open_on_click` is set
+/// and closes when clicking outside of it or pressing escape.
+pub fn setup(
+    widget: &impl IsA<Widget>,
+    content: &impl IsA<Widget>,
+    name: &str,
+    open_on_click: bool,
+) -> Popover {
+    let popover = Popover::builder()
+        .child(content)
+        .autohide(true)
+        .has_arrow(false)
+        .position(PositionType::Bottom)
+        .build();
+
+    popover.add_css_class("module-popover");
+    popover.add_css_class(name);
+    content.add_css_class("content");
+
+    popover.set_parent(widget);
+
+    // Layer shell surfaces don't get keyboard focus by default, which the
+    // popover needs to be dismissed with escape or by clicking outside.
+    popover.connect_show(|popover| set_keyboard_mode(popover, KeyboardMode::OnDemand));
+    popover.connect_closed(|popover| set_keyboard_mode(popover, KeyboardMode::None));
+
+    // A popover has to be removed before its parent goes away.
+    widget.connect_destroy({
+        let popover = popover.clone();
+        move |_| popover.unparent()
+    });
+
+    if open_on_click {
+        let click = GestureClick::new();
+        click.set_propagation_phase(PropagationPhase::Capture);
+        click.set_button(1);
+
+        let popover = popover.clone();
+        click.connect_released(move |_, _, _, _| {
+            if popover.is_visible() {
+                popover.popdown();
+            } else {
+                popover.popup();
+            }
+        });
+        widget.add_controller(click);
+    }
+
+    popover
+}
+
+fn set_keyboard_mode(popover: &Popover, mode: KeyboardMode) {
+    let window = popover.parent().and_then(|parent| parent.root());
+    if let Some(window) = window.and_downcast::<gtk::Window>() {
+        window.set_keyboard_mode(mode);
+    }
+}
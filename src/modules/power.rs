@@ -1,20 +1,53 @@
-use std::{ops::Mul, time::Duration};
+use std::time::Duration;
 
 use gtk::{glib, prelude::*, Box, Label};
 use serde::Deserialize;
+use tokio::sync::mpsc;
 use tracing::error;
 
-use crate::rbar::RBar;
+use crate::{rbar::RBar, sources::Publisher};
 
-use super::{BaseModuleConfig, Events, Module};
+use super::{
+    display::Display,
+    format::{Arg, Context, IconRamp, Template},
+    icon::{self, IconWidget},
+    states::{Direction, States},
+    BaseModuleConfig, Events, Module, WidgetContext,
+};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Power {
     config: BaseModuleConfig,
 
-    /// Precision of the percentage.
+    /// Precision of the percentage in the default format.
     #[serde(default = "precision_default")]
     precision: u8,
+
+    /// Format of the label.
+    /// Default: `{percentage:.<precision>}%`
+    ///
+    /// See [Power::format_context] for available values.
+    #[serde(default)]
+    format: Option<Template>,
+
+    /// Icons by state of charge, shown next to the label and as `{icon}`.
+    /// Glyphs or SVGs, see [icon](super::icon).
+    #[serde(default = "icons_default")]
+    icons: IconRamp,
+
+    /// Icon while charging.
+    #[serde(default = "icon_charging_default")]
+    icon_charging: String,
+
+    /// Which icons to show, see [IconStyle].
+    #[serde(default)]
+    icon_style: IconStyle,
+
+    /// States used if none are configured, `critical` at 10%.
+    ///
+    /// A discharging battery in the `critical` state requests attention.
+    #[serde(skip, default = "states_default")]
+    default_states: States,
 }
 
 impl Module<Box> for Power {
@@ -25,104 +58,265 @@ impl Module<Box> for Power {
         "power"
     }
 
-    fn controllers(&self, context: &super::WidgetContext<Self::Send>) -> crate::Result<()> {
+    fn controllers(
+        &self,
+        context: &WidgetContext<Self::Send, Self::Receive>,
+        _rx: mpsc::Receiver<Self::Receive>,
+    ) -> crate::Result<()> {
         let manager = battery::Manager::new();
 
-        let mut battery = manager
+        let battery = manager
             .batteries()?
             .into_iter()
             .next()
             .ok_or("Failed to get battery")?;
 
+        // Read the battery once for all bars.
+        let key = battery.root.display().to_string();
+        let mut source = RBar::sources().subscribe(key, move |publisher| {
+            read_battery(battery.clone(), publisher)
+        });
+
         let tx = context.tx.clone();
-        RBar::runtime().spawn(async move {
-            let duration = Duration::from_secs(1);
+        let module = self.clone();
+        let lifecycle = context.lifecycle.clone();
+        context.lifecycle.spawn(async move {
+            let mut available = true;
+            let mut attention = false;
+
+            while let Some(battery) = source.next().await {
+                let mut events = Vec::new();
 
-            while let Ok(battery) = battery.refresh() {
-                if let Err(e) = tx.send(Events::Update(battery.clone())).await {
-                    error!("Failed to send battery update: {}", e);
-                    break;
+                match battery {
+                    Ok(battery) => {
+                        let is_critical = battery.state() == &battery::State::Discharging
+                            && module.level(&battery) == Some("critical");
+                        if is_critical != attention {
+                            attention = is_critical;
+                            events.push(Events::Attention(attention));
+                        }
+
+                        if !available {
+                            available = true;
+                            events.push(Events::Visibility(true));
+                        }
+                        events.push(Events::Update(battery));
+                    }
+                    // Hide the module while the battery can't be read, e.g. it got removed.
+                    Err(e) => {
+                        events.push(Events::Error(e));
+                        if available {
+                            available = false;
+                            events.push(Events::Visibility(false));
+                        }
+                    }
+                }
+
+                for event in events {
+                    if let Err(e) = tx.send(event).await {
+                        error!("Failed to send battery update: {}", e);
+                        return;
+                    }
                 }
 
-                tokio::time::sleep(duration).await;
+                lifecycle.running().await;
             }
         });
 
         Ok(())
     }
 
-    fn widget(&self, context: super::WidgetContext<Self::Send>) -> crate::Result<Box> {
+    fn widget(&self, context: WidgetContext<Self::Send, Self::Receive>) -> crate::Result<Box> {
         let container = Box::new(gtk::Orientation::Horizontal, 0);
-        let icon = Label::new(None);
+        let icon = IconWidget::new();
         let label = Label::new(None);
 
-        icon.add_css_class("icon");
         label.add_css_class("label");
 
-        container.append(&icon);
+        container.append(icon.widget());
         container.append(&label);
         container.show();
 
-        let precision = self.precision;
+        // The indicator of the `display` takes the place of the label.
+        label.set_visible(self.config.display == Display::Text);
+
+        let format = match &self.format {
+            Some(format) => format.clone(),
+            None => format!("{{percentage:.{}}}%", self.precision).parse()?,
+        };
+        let module = self.clone();
 
         let mut rx = context.subscribe();
         glib::spawn_future_local(async move {
-            let classes = ["charging", "discharging", "full", "unknown"];
-
-            let container = label.parent().unwrap();
+            while let Some(battery) = rx.recv().await {
+                let context = module.template_context(&battery);
+                label.set_markup(&format.render(&context));
 
-            while let Ok(battery) = rx.recv().await {
-                use battery::State;
-
-                let class_index = match battery.state() {
-                    State::Charging => 0,
-                    State::Discharging => 1,
-                    State::Full => 2,
-                    _ => 3,
-                };
-
-                for (i, class) in classes.iter().enumerate() {
-                    container.remove_css_class(class);
-                    if i == class_index {
-                        container.add_css_class(class);
+                match module.icon_style {
+                    IconStyle::Glyph | IconStyle::Theme => set_icon(&icon, &context),
+                    IconStyle::Battery => {
+                        icon.set(&icon::battery(battery.state_of_charge().into()))
                     }
                 }
-
-                format_label(&icon, &label, &battery, precision);
             }
         });
 
         Ok(container)
     }
 
+    /// Available: `{percentage}`, `{icon}` shown next to the label, `{icon_name}` of the
+    /// icon theme, `{state}`, `{charging}`, `{full}`, `{rate}` in W, `{energy}` in Wh and
+    /// `{time_left}` as `H:MM`.
+    fn format_context(&self, battery: &Self::Send) -> Context {
+        let soc = battery.state_of_charge();
+
+        let icon = match self.icon_style {
+            IconStyle::Theme => format!("icon:{}", icon_name(battery)),
+            _ if battery.is_charging() => self.icon_charging.clone(),
+            _ => self.icons.get(soc.into()).to_string(),
+        };
+
+        let time_left = battery.time_left().map_or_else(String::new, hours_minutes);
+
+        Context::new()
+            .with("percentage", soc)
+            .with("icon", icon)
+            .with("icon_name", icon_name(battery))
+            .with("state", state_name(battery.state()))
+            .with("charging", battery.is_charging())
+            .with("full", battery.is_full())
+            .with("rate", battery.energy_rate())
+            .with("energy", battery.energy())
+            .with("time_left", time_left)
+    }
+
+    fn value(&self, battery: &Self::Send) -> Option<f64> {
+        Some(battery.state_of_charge().into())
+    }
+
+    /// The rate and the time until the battery is empty or full, e.g. `7.5 W, 3:20 left`.
+    fn tooltip(&self, battery: &Self::Send) -> Option<String> {
+        if battery.energy_rate() <= 0.0 {
+            return None;
+        }
+        let rate = format!("{:.1} W", battery.energy_rate());
+
+        let Some(time_left) = battery.time_left() else {
+            return Some(rate);
+        };
+
+        Some(match battery.state() {
+            battery::State::Charging => format!("{}, full in {}", rate, hours_minutes(time_left)),
+            _ => format!("{}, {} left", rate, hours_minutes(time_left)),
+        })
+    }
+
+    fn state_direction(&self) -> Direction {
+        Direction::Below
+    }
+
+    fn states(&self) -> Option<&States> {
+        self.config.states.as_ref().or(Some(&self.default_states))
+    }
+
+    /// The state of the battery, e.g. `charging`.
+    fn classes(&self, battery: &Self::Send) -> Vec<String> {
+        vec![state_name(battery.state()).to_string()]
+    }
+
     fn get_base_config(&self) -> &BaseModuleConfig {
         &self.config
     }
 }
 
+/// Source reading the battery every second.
+async fn read_battery(
+    mut battery: battery::Battery,
+    publisher: Publisher<Result<battery::Battery, String>>,
+) {
+    let duration = Duration::from_secs(1);
+
+    loop {
+        let battery = battery
+            .refresh()
+            .cloned()
+            .map_err(|e| format!("Failed to read battery: {}", e));
+        publisher.send(battery);
+
+        publisher.sleep(duration).await;
+    }
+}
+
+/// [IconStyle] selects the icon next to the label.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum IconStyle {
+    /// The `icons` and `icon_charging`.
+    #[default]
+    Glyph,
+    /// A battery filled up to the state of charge.
+    Battery,
+    /// The battery icons of the icon theme, `icon:{icon_name}`.
+    Theme,
+}
+
 fn precision_default() -> u8 {
     0
 }
 
-fn format_label(icon: &Label, label: &Label, battery: &battery::Battery, precision: u8) {
-    let soc = battery.state_of_charge();
+fn states_default() -> States {
+    States {
+        direction: None,
+        thresholds: [("critical".to_string(), 10.0)].into(),
+    }
+}
+
+fn icons_default() -> IconRamp {
+    IconRamp::steps([(10.0, ""), (40.0, ""), (60.0, ""), (80.0, ""), (100.0, "")])
+}
+
+fn icon_charging_default() -> String {
+    "".to_string()
+}
+
+/// Name of the freedesktop icon for the battery, e.g. `battery-level-40-charging-symbolic`.
+fn icon_name(battery: &battery::Battery) -> String {
+    if battery.is_full() {
+        return "battery-level-100-charged-symbolic".to_string();
+    }
 
-    let icon_text = if battery.is_charging() {
-        ""
+    let level = (battery.state_of_charge() / 10.0).round() as u8 * 10;
+    let charging = if battery.is_charging() {
+        "-charging"
     } else {
-        match soc {
-            0.0..=10.0 => "",
-            11.0..=40.0 => "",
-            41.0..=60.0 => "",
-            61.0..=80.0 => "",
-            81.0..=100.0 => "",
-            _ => "",
-        }
+        ""
     };
 
-    icon.set_label(icon_text);
+    format!("battery-level-{}{}-symbolic", level.min(100), charging)
+}
 
-    let label_text = format!("{:.1$}%", soc, precision as usize);
+/// Format a duration as `H:MM`.
+fn hours_minutes(duration: Duration) -> String {
+    let minutes = duration.as_secs() / 60;
+    format!("{}:{:02}", minutes / 60, minutes % 60)
+}
+
+fn state_name(state: &battery::State) -> &'static str {
+    use battery::State;
+
+    match state {
+        State::Charging => "charging",
+        State::Discharging => "discharging",
+        State::Full => "full",
+        State::Unknown => "unknown",
+    }
+}
+
+fn set_icon(icon: &IconWidget, context: &Context) {
+    let icon_text = match context.get("icon") {
+        Some(Arg::Text(icon)) => icon.as_str(),
+        _ => "",
+    };
 
-    label.set_label(&label_text);
+    icon.set_text(icon_text);
 }
@@ -0,0 +1,94 @@
+//! Named states of numeric modules, like `warning` and `critical`.
+//!
+//! A module provides a value with [Module::value](super::Module::value),
+//! e.g. the state of charge of a battery. The active state is added as a class
+//! to the widget and available to templates as `{level}`.
+
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+/// [States] maps names to thresholds.
+///
+/// Example: `{"warning": 30, "critical": 15, "direction": "below"}`
+#[derive(Debug, Clone, Deserialize)]
+pub struct States {
+    /// Whether a state is active above or below its threshold.
+    /// Defaults to the direction of the module.
+    #[serde(default)]
+    pub direction: Option<Direction>,
+
+    #[serde(flatten)]
+    pub thresholds: BTreeMap<String, f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// Active at or above the threshold, e.g. for the CPU usage.
+    Above,
+    /// Active at or below the threshold, e.g. for the battery.
+    Below,
+}
+
+impl States {
+    /// Get the active state, the one whose threshold the value passed furthest.
+    pub fn get(&self, value: f64, direction: Direction) -> Option<&str> {
+        let direction = self.direction.unwrap_or(direction);
+        let thresholds = self.thresholds.iter();
+
+        let state = match direction {
+            Direction::Above => thresholds
+                .filter(|(_, threshold)| value >= **threshold)
+                .max_by(|(_, a), (_, b)| a.total_cmp(b)),
+            Direction::Below => thresholds
+                .filter(|(_, threshold)| value <= **threshold)
+                .min_by(|(_, a), (_, b)| a.total_cmp(b)),
+        };
+
+        state.map(|(name, _)| name.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn states(json: &str) -> States {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn above() {
+        let states = states(r#"{ "warning": 70, "critical": 90 }"#);
+
+        assert_eq!(states.get(50.0, Direction::Above), None);
+        assert_eq!(states.get(70.0, Direction::Above), Some("warning"));
+        assert_eq!(states.get(89.9, Direction::Above), Some("warning"));
+        assert_eq!(states.get(95.0, Direction::Above), Some("critical"));
+    }
+
+    #[test]
+    fn below() {
+        let states = states(r#"{ "warning": 30, "critical": 15 }"#);
+
+        assert_eq!(states.get(50.0, Direction::Below), None);
+        assert_eq!(states.get(30.0, Direction::Below), Some("warning"));
+        assert_eq!(states.get(15.0, Direction::Below), Some("critical"));
+        assert_eq!(states.get(0.0, Direction::Below), Some("critical"));
+    }
+
+    #[test]
+    fn configured_direction() {
+        let states = states(r#"{ "low": 20, "direction": "below" }"#);
+
+        assert_eq!(states.get(10.0, Direction::Above), Some("low"));
+        assert_eq!(states.get(30.0, Direction::Above), None);
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(serde_json::from_str::<States>(r#"{ "warning": "high" }"#).is_err());
+        assert!(serde_json::from_str::<States>(r#"{ "direction": "up" }"#).is_err());
+    }
+}
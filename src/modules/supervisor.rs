@@ -0,0 +1,82 @@
+//! Restart modules whose controllers fail.
+//!
+//! The controllers of a module run with their own [Lifecycle](super::lifecycle::Lifecycle).
+//! If [Module::controllers] returns an error or one of its tasks panics, the module
+//! shows its error state and the controllers are started again after a delay,
+//! which doubles with every failure in a row.
+
+use std::time::{Duration, Instant};
+
+use gtk::{glib, prelude::*, Widget};
+use tokio::sync::mpsc;
+use tracing::debug;
+
+use super::{Events, Module, WidgetContext};
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Run the controllers of the module and restart them when they fail.
+///
+/// Messages of the widget received on `rx` are passed on to the current controllers.
+pub fn supervise<M, W>(
+    module: &M,
+    context: &WidgetContext<M::Send, M::Receive>,
+    mut rx: mpsc::Receiver<M::Receive>,
+) where
+    M: Module<W> + Clone + 'static,
+    W: IsA<Widget>,
+{
+    let module = module.clone();
+    let context = context.clone();
+
+    glib::spawn_future_local(async move {
+        let mut backoff = MIN_BACKOFF;
+
+        loop {
+            let started = Instant::now();
+            let run = context.lifecycle.child();
+            let (tx, run_rx) = mpsc::channel(16);
+            let run_context = WidgetContext {
+                lifecycle: run.clone(),
+                ..context.clone()
+            };
+
+            let failure = match module.controllers(&run_context, run_rx) {
+                Ok(()) => loop {
+                    tokio::select! {
+                        Some(message) = rx.recv() => {
+                            if tx.try_send(message).is_err() {
+                                debug!("Dropping message for busy module '{}'", module.module_name());
+                            }
+                        }
+                        failure = run.failed() => break failure,
+                        _ = context.lifecycle.stopped() => return,
+                    }
+                },
+                Err(e) => e.to_string(),
+            };
+            run.stop();
+
+            // Start over with a short delay if the module ran fine for a while.
+            if started.elapsed() > MAX_BACKOFF {
+                backoff = MIN_BACKOFF;
+            }
+
+            // Nothing to show if the module is stopping.
+            let _ = context.tx.send(Events::Error(failure)).await;
+
+            debug!(
+                "Restarting module '{}' ({}) in {}s",
+                module.module_name(),
+                context.id,
+                backoff.as_secs()
+            );
+            tokio::select! {
+                _ = glib::timeout_future(backoff) => {}
+                _ = context.lifecycle.stopped() => return,
+            }
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    });
+}
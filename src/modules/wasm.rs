@@ -0,0 +1,173 @@
+use std::{num::NonZeroU64, path::PathBuf};
+
+use gtk::Label;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use tokio::{sync::mpsc, task, time::Duration};
+use tracing::error;
+
+use crate::{
+    config::Config,
+    rbar::RBar,
+    sources::Publisher,
+    wasm::{Capabilities, Runtime},
+};
+
+use super::{
+    custom::{forward_outputs, output_label, CustomOutput},
+    format::Context,
+    BaseModuleConfig, Module, WidgetContext,
+};
+
+/// A module running sandboxed WebAssembly, see [crate::wasm].
+#[derive(Debug, Clone, Deserialize)]
+pub struct Wasm {
+    config: BaseModuleConfig,
+
+    /// Path of the module, relative to `~/.config/rbar/plugins`.
+    pub path: PathBuf,
+
+    /// Seconds between two `tick` events, at least 1.
+    #[serde(default = "default_interval")]
+    pub interval: NonZeroU64,
+
+    /// What the module is allowed to access.
+    #[serde(default)]
+    pub allow: Capabilities,
+
+    /// Options passed to the module with the `init` event.
+    #[serde(default)]
+    pub options: Map<String, Value>,
+}
+
+fn default_interval() -> NonZeroU64 {
+    NonZeroU64::new(60).expect("default interval to be non-zero")
+}
+
+impl Module<Label> for Wasm {
+    type Receive = ();
+    type Send = CustomOutput;
+
+    fn name() -> &'static str {
+        "wasm"
+    }
+
+    fn controllers(
+        &self,
+        context: &WidgetContext<Self::Send, Self::Receive>,
+        rx: mpsc::Receiver<Self::Receive>,
+    ) -> crate::Result<()> {
+        let path = Config::get_dir().join("plugins").join(&self.path);
+        let duration = Duration::from_secs(self.interval.get());
+        let capabilities = self.allow.clone();
+        let options = self.options.clone();
+
+        // Run the module once for all bars.
+        let key = format!("{:?}", (&path, self.interval, &capabilities, &options));
+        let source = RBar::sources().subscribe(key, move |publisher| {
+            run(
+                path.clone(),
+                capabilities.clone(),
+                options.clone(),
+                duration,
+                publisher,
+            )
+        });
+
+        forward_outputs(context, rx, source, self.path.display().to_string());
+
+        Ok(())
+    }
+
+    fn widget(&self, context: WidgetContext<Self::Send, Self::Receive>) -> crate::Result<Label> {
+        Ok(output_label(&context))
+    }
+
+    fn format_context(&self, output: &Self::Send) -> Context {
+        output.context()
+    }
+
+    fn value(&self, output: &Self::Send) -> Option<f64> {
+        output.value()
+    }
+
+    fn tooltip(&self, output: &Self::Send) -> Option<String> {
+        output.tooltip()
+    }
+
+    fn classes(&self, output: &Self::Send) -> Vec<String> {
+        output.class.clone()
+    }
+
+    fn is_visible(&self, output: &Self::Send) -> bool {
+        output.is_visible()
+    }
+
+    fn action(
+        &self,
+        name: &str,
+        context: &WidgetContext<Self::Send, Self::Receive>,
+    ) -> crate::Result<()> {
+        match name {
+            "refresh" => Ok(context.controller_tx.try_send(())?),
+            _ => Err(format!("Module '{}' has no action '{}'", Self::name(), name).into()),
+        }
+    }
+
+    fn get_base_config(&self) -> &BaseModuleConfig {
+        &self.config
+    }
+}
+
+/// Source sending a `tick` event to the module every `duration`.
+///
+/// The module runs on a blocking thread, host functions like `run` block. The
+/// source ends if the module traps, e.g. by running out of fuel.
+async fn run(
+    path: PathBuf,
+    capabilities: Capabilities,
+    options: Map<String, Value>,
+    duration: Duration,
+    publisher: Publisher<Result<CustomOutput, String>>,
+) {
+    let init = task::spawn_blocking(move || {
+        Runtime::new(&path, capabilities, &options)
+            .map_err(|e| format!("Failed to load '{}': {}", path.display(), e))
+    });
+
+    let mut runtime = match init.await {
+        Ok(Ok((runtime, output))) => {
+            send(&publisher, output);
+            runtime
+        }
+        Ok(Err(e)) => return publisher.send(Err(e)),
+        Err(e) => return error!("Failed to load wasm module: {}", e),
+    };
+
+    loop {
+        let refreshed = publisher.sleep(duration).await;
+        let event = if refreshed { "refresh" } else { "tick" };
+
+        let handled = task::spawn_blocking(move || {
+            let output = runtime
+                .handle(&json!({ "event": event }))
+                .map_err(|e| e.to_string());
+            (runtime, output)
+        });
+
+        match handled.await {
+            Ok((handled, Ok(output))) => {
+                runtime = handled;
+                send(&publisher, output);
+            }
+            Ok((_, Err(e))) => return publisher.send(Err(e)),
+            Err(e) => return error!("Failed to run wasm module: {}", e),
+        }
+    }
+}
+
+fn send(publisher: &Publisher<Result<CustomOutput, String>>, output: Option<String>) {
+    if let Some(output) = output {
+        publisher.send(Ok(CustomOutput::parse(&output)));
+    }
+}
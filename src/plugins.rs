@@ -0,0 +1,307 @@
+//! Plugin modules loaded from shared libraries.
+//!
+//! Every `*.so` in `~/.config/rbar/plugins` is loaded at startup and checked
+//! against the ABI of [rbar_plugin]. Plugins are never unloaded, GTK may still
+//! reference their code after their modules are gone.
+
+use std::{
+    collections::HashMap,
+    ffi::{c_char, c_void, CStr, CString, OsStr},
+    fmt,
+    os::unix::ffi::OsStrExt,
+    path::Path,
+    ptr,
+    sync::Arc,
+};
+
+use rbar_plugin::{Declaration, Host, ABI_VERSION, DECLARATION_SYMBOL};
+use serde_json::{Map, Value};
+use tracing::{debug, error};
+
+/// [Plugins] holds all loaded plugins by name.
+#[derive(Default)]
+pub struct Plugins {
+    plugins: HashMap<String, Arc<Plugin>>,
+}
+
+impl Plugins {
+    /// Load all plugins in the directory. Plugins failing to load are skipped.
+    pub fn load(dir: &Path) -> Self {
+        let mut plugins = HashMap::new();
+
+        let Ok(entries) = dir.read_dir() else {
+            return Self { plugins };
+        };
+
+        for path in entries.flatten().map(|entry| entry.path()) {
+            if path.extension() != Some(OsStr::new("so")) {
+                continue;
+            }
+
+            match Plugin::open(&path) {
+                Ok(plugin) if plugins.contains_key(&plugin.name) => {
+                    error!(
+                        "Skipping plugin '{}', '{}' is already loaded",
+                        path.display(),
+                        plugin.name
+                    );
+                }
+                Ok(plugin) => {
+                    debug!("Loaded plugin '{}' from '{}'", plugin.name, path.display());
+                    plugins.insert(plugin.name.clone(), Arc::new(plugin));
+                }
+                Err(e) => error!("Failed to load plugin '{}': {}", path.display(), e),
+            }
+        }
+
+        Self { plugins }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Arc<Plugin>> {
+        self.plugins.get(name)
+    }
+
+    /// Names of all loaded plugins, sorted.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        let mut names: Vec<&str> = self.plugins.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names.into_iter()
+    }
+}
+
+impl fmt::Debug for Plugins {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.plugins.keys()).finish()
+    }
+}
+
+/// [Plugin] is a loaded plugin, providing a single module.
+pub struct Plugin {
+    pub name: String,
+    schema: Option<Value>,
+    declaration: &'static Declaration,
+}
+
+impl Plugin {
+    fn open(path: &Path) -> crate::Result<Self> {
+        let path = CString::new(path.as_os_str().as_bytes())?;
+
+        // Safety: loading a library runs its initializers, plugins are trusted.
+        let declaration = unsafe {
+            let library = libc::dlopen(path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL);
+            if library.is_null() {
+                return Err(dl_error().into());
+            }
+
+            let symbol = libc::dlsym(library, DECLARATION_SYMBOL.as_ptr());
+            if symbol.is_null() {
+                return Err(format!("Missing symbol '{:?}'", DECLARATION_SYMBOL).into());
+            }
+
+            &*symbol.cast::<Declaration>()
+        };
+
+        if declaration.abi_version != ABI_VERSION {
+            return Err(format!(
+                "Plugin ABI version {} is not supported, expected {}",
+                declaration.abi_version, ABI_VERSION
+            )
+            .into());
+        }
+
+        let name = unsafe { string(declaration.name) }.ok_or("Plugin has no name")?;
+        let schema = match unsafe { string(declaration.config_schema) } {
+            Some(schema) => Some(
+                serde_json::from_str(&schema)
+                    .map_err(|e| format!("Invalid config schema of '{}': {}", name, e))?,
+            ),
+            None => None,
+        };
+
+        Ok(Self {
+            name,
+            schema,
+            declaration,
+        })
+    }
+
+    /// Check the options against the config schema of the plugin.
+    ///
+    /// Supports `required`, `properties` with their `type`, and `additionalProperties: false`.
+    pub fn validate(&self, options: &Map<String, Value>) -> Result<(), String> {
+        let Some(schema) = &self.schema else {
+            return Ok(());
+        };
+
+        validate(schema, options)
+    }
+
+    /// Create a module with the given options.
+    pub fn create(
+        self: &Arc<Self>,
+        options: &Map<String, Value>,
+        host: Host,
+    ) -> crate::Result<Instance> {
+        self.validate(options)?;
+
+        let options = CString::new(Value::Object(options.clone()).to_string())?;
+        let mut error = ptr::null_mut();
+
+        let module = unsafe { (self.declaration.create)(options.as_ptr(), host, &mut error) };
+        if module.is_null() {
+            return Err(self.take_error(error).into());
+        }
+
+        Ok(Instance {
+            plugin: self.clone(),
+            module,
+        })
+    }
+
+    /// Get an error returned by the plugin and free it.
+    fn take_error(&self, error: *mut c_char) -> String {
+        let message = unsafe { string(error) }.unwrap_or_else(|| "Unknown error".to_string());
+        unsafe { (self.declaration.free_string)(error) };
+        message
+    }
+}
+
+/// [Instance] is a module created by a plugin. It is destroyed once dropped.
+pub struct Instance {
+    plugin: Arc<Plugin>,
+    module: *mut c_void,
+}
+
+impl Instance {
+    /// Create the widget of the module. Returns a `GtkWidget` pointer with a full reference.
+    pub fn widget(&self) -> *mut c_void {
+        unsafe { (self.plugin.declaration.widget)(self.module) }
+    }
+
+    /// Run a module-defined action.
+    pub fn action(&self, name: &str) -> crate::Result<()> {
+        let name = CString::new(name)?;
+        let mut error = ptr::null_mut();
+
+        match unsafe { (self.plugin.declaration.action)(self.module, name.as_ptr(), &mut error) } {
+            true => Ok(()),
+            false => Err(self.plugin.take_error(error).into()),
+        }
+    }
+}
+
+impl Drop for Instance {
+    fn drop(&mut self) {
+        unsafe { (self.plugin.declaration.destroy)(self.module) }
+    }
+}
+
+/// Check options against a JSON schema, see [Plugin::validate].
+fn validate(schema: &Value, options: &Map<String, Value>) -> Result<(), String> {
+    let required = schema.get("required").and_then(Value::as_array);
+    for name in required.into_iter().flatten().filter_map(Value::as_str) {
+        if !options.contains_key(name) {
+            return Err(format!("Missing option '{}'", name));
+        }
+    }
+
+    let properties = schema.get("properties").and_then(Value::as_object);
+    let additional = schema.get("additionalProperties") != Some(&Value::Bool(false));
+
+    for (name, value) in options {
+        let Some(property) = properties.and_then(|properties| properties.get(name)) else {
+            if additional {
+                continue;
+            }
+            return Err(format!("Unknown option '{}'", name));
+        };
+
+        let Some(expected) = property.get("type").and_then(Value::as_str) else {
+            continue;
+        };
+
+        let matches = match expected {
+            "string" => value.is_string(),
+            "number" => value.is_number(),
+            "integer" => value.is_i64() || value.is_u64(),
+            "boolean" => value.is_boolean(),
+            "array" => value.is_array(),
+            "object" => value.is_object(),
+            "null" => value.is_null(),
+            _ => true,
+        };
+        if !matches {
+            return Err(format!("Option '{}' must be of type {}", name, expected));
+        }
+    }
+
+    Ok(())
+}
+
+/// Copy a C string, `None` if it is null.
+unsafe fn string(string: *const c_char) -> Option<String> {
+    (!string.is_null()).then(|| CStr::from_ptr(string).to_string_lossy().into_owned())
+}
+
+fn dl_error() -> String {
+    let error = unsafe { libc::dlerror() };
+    unsafe { string(error) }.unwrap_or_else(|| "Unknown error".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn check(schema: Value, options: Value) -> Result<(), String> {
+        validate(&schema, options.as_object().unwrap())
+    }
+
+    #[test]
+    fn required() {
+        let schema = json!({ "required": ["city"] });
+
+        assert!(check(schema.clone(), json!({ "city": "Berlin" })).is_ok());
+        assert_eq!(
+            check(schema, json!({})),
+            Err("Missing option 'city'".to_string())
+        );
+    }
+
+    #[test]
+    fn types() {
+        let schema = json!({
+            "properties": {
+                "city": { "type": "string" },
+                "days": { "type": "integer" },
+                "scale": { "type": "number" },
+                "metric": { "type": "boolean" },
+                "any": {},
+            }
+        });
+
+        let valid = json!({ "city": "Berlin", "days": 3, "scale": 1.5, "metric": true, "any": [] });
+        assert!(check(schema.clone(), valid).is_ok());
+        assert!(check(schema.clone(), json!({ "days": 1.5 })).is_err());
+        assert!(check(schema.clone(), json!({ "scale": "1" })).is_err());
+        assert_eq!(
+            check(schema, json!({ "metric": "yes" })),
+            Err("Option 'metric' must be of type boolean".to_string())
+        );
+    }
+
+    #[test]
+    fn additional_properties() {
+        let properties = json!({ "city": { "type": "string" } });
+
+        assert!(check(json!({ "properties": properties }), json!({ "other": 1 })).is_ok());
+        assert_eq!(
+            check(
+                json!({ "properties": properties, "additionalProperties": false }),
+                json!({ "other": 1 })
+            ),
+            Err("Unknown option 'other'".to_string())
+        );
+    }
+}
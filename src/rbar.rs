@@ -1,31 +1,52 @@
 use std::{
-    path::PathBuf,
+    cell::{OnceCell, RefCell},
+    collections::HashSet,
+    rc::Rc,
     sync::{
         atomic::{AtomicUsize, Ordering},
         Arc, OnceLock,
     },
 };
 
-use gtk::{glib::ExitCode, prelude::*, Application};
-use tokio::runtime::Runtime;
-use tracing::error;
+use gtk::{gio, glib, glib::ExitCode, prelude::*, Application, CssProvider};
+use tokio::{
+    runtime::Runtime,
+    sync::{broadcast, mpsc},
+};
+use tracing::{debug, error, warn};
 
-use crate::style;
+use crate::{
+    bar::Bar,
+    dbus,
+    idle::{self, PauseReason},
+    ipc::{self, Request, Response},
+    modules::{ModuleEvent, ModuleHandle},
+    plugins::Plugins,
+    scheduler::Scheduler,
+    sources::Sources,
+    style,
+};
 
 use crate::{bar::load_bars, config::Config};
 
-const APP_ID: &str = "com.migueldamota.rbar";
+pub const APP_ID: &str = "com.migueldamota.rbar";
 
 #[derive(Debug)]
 pub struct RBar {
     pub config: Config,
-    pub config_dir: PathBuf,
+    pub plugins: Plugins,
 }
 
 impl RBar {
-    pub fn new() -> Self {
-        let (config, config_dir) = Config::load();
-        Self { config, config_dir }
+    pub fn new() -> crate::Result<Self> {
+        let config = Config::load()?;
+        let plugins = Plugins::load(&Config::get_dir().join("plugins"));
+
+        for module in config.bar.modules.iter() {
+            module.validate(&plugins)?;
+        }
+
+        Ok(Self { config, plugins })
     }
 
     /// Start the rbar bar.
@@ -33,17 +54,58 @@ impl RBar {
         let app = Application::builder().application_id(APP_ID).build();
 
         let instance = Arc::new(self);
+        let state = OnceCell::new();
 
         app.connect_activate(move |app| {
+            // Only set up once, even if activated again.
+            if state.get().is_some() {
+                return;
+            }
+
             // Load styles.
-            style::init();
+            let style = style::init();
 
             // Load bars.
-            if let Err(e) = load_bars(instance.clone(), app) {
+            let bars = load_bars(instance.clone(), app).unwrap_or_else(|e| {
                 error!("Failed to load bars: {}", e);
+                Vec::new()
+            });
+
+            let app_state = Rc::new(RefCell::new(AppState {
+                app: app.clone(),
+                rbar: instance.clone(),
+                style,
+                bars,
+                _timezone: RBar::scheduler().watch_timezone(),
+                paused: HashSet::new(),
+            }));
+
+            // Listen for ipc requests.
+            let (tx, rx) = mpsc::channel(32);
+            let socket = RefCell::new(ipc::server::start(tx));
+            app.connect_shutdown(move |_| drop(socket.take()));
+            AppState::setup_receiver(app_state.clone(), rx);
+
+            // Expose the same requests on the session bus.
+            if let Err(e) = dbus::register(app, app_state.clone()) {
+                warn!("Failed to register D-Bus interface: {}", e);
             }
+
+            // Pause updates while the system sleeps or the screens are off.
+            idle::watch(app, app_state.clone());
+
+            let _ = state.set(app_state);
         });
 
+        // Exit cleanly on SIGINT and SIGTERM, removing the control socket.
+        for signum in [libc::SIGINT, libc::SIGTERM] {
+            let app = app.clone();
+            glib::unix_signal_add_local(signum, move || {
+                app.quit();
+                glib::ControlFlow::Continue
+            });
+        }
+
         // Let's run it.
         Ok(app.run())
     }
@@ -60,6 +122,171 @@ impl RBar {
         static RUNTIME: OnceLock<Arc<Runtime>> = OnceLock::new();
         RUNTIME.get_or_init(|| Arc::new(create_runtime())).clone()
     }
+
+    /// Get the channel named refresh triggers are published on.
+    ///
+    /// Triggers are sent with `rbar msg trigger <name>`.
+    pub fn triggers() -> broadcast::Sender<String> {
+        static TRIGGERS: OnceLock<broadcast::Sender<String>> = OnceLock::new();
+        TRIGGERS.get_or_init(|| broadcast::channel(16).0).clone()
+    }
+
+    /// Get the channel every module update is published on.
+    pub fn events() -> broadcast::Sender<ModuleEvent> {
+        static EVENTS: OnceLock<broadcast::Sender<ModuleEvent>> = OnceLock::new();
+        EVENTS.get_or_init(|| broadcast::channel(64).0).clone()
+    }
+
+    /// Get the scheduler of wall-clock timers.
+    pub fn scheduler() -> &'static Scheduler {
+        static SCHEDULER: OnceLock<Scheduler> = OnceLock::new();
+        SCHEDULER.get_or_init(Scheduler::default)
+    }
+
+    /// Get the registry of data sources shared by all modules.
+    pub fn sources() -> &'static Sources {
+        static SOURCES: OnceLock<Sources> = OnceLock::new();
+        SOURCES.get_or_init(Sources::default)
+    }
+}
+
+/// [AppState] holds everything owned by the running application on the GTK main thread.
+#[derive(Debug)]
+pub struct AppState {
+    app: Application,
+    rbar: Arc<RBar>,
+    style: CssProvider,
+    bars: Vec<Bar>,
+    /// Keeps watching the timezone for the [Scheduler].
+    _timezone: Option<gio::FileMonitor>,
+    /// Reasons updates are currently paused for.
+    paused: HashSet<PauseReason>,
+}
+
+impl AppState {
+    /// Handle a request, e.g. sent over the control socket.
+    pub fn handle(&mut self, request: Request) -> Response {
+        debug!("Handling request: {:?}", request);
+
+        match request {
+            Request::ReloadConfig => self.reload_config().into(),
+            Request::ReloadStyle => {
+                style::reload(&self.style);
+                Response::ok()
+            }
+            Request::ToggleBar { output } => match self.bar(&output) {
+                Some(bar) => {
+                    bar.toggle();
+                    Response::ok()
+                }
+                None => Response::error(format!("No bar on output '{}'", output)),
+            },
+            Request::ListModules => {
+                Response::data(self.modules().map(ModuleHandle::info).collect::<Vec<_>>())
+            }
+            Request::ModuleState { id } => match self.module(&id) {
+                Some(module) => Response::data(module.state()),
+                None => Response::error(format!("No module with id '{}'", id)),
+            },
+            Request::ModuleAction { id, action } => match self.module(&id) {
+                Some(module) => module.action(&action).into(),
+                None => Response::error(format!("No module with id '{}'", id)),
+            },
+            Request::Trigger { name } => {
+                // Modules waiting for the trigger might not be loaded.
+                let _ = RBar::triggers().send(name);
+                Response::ok()
+            }
+            Request::Pause => {
+                self.set_paused(PauseReason::Request, true);
+                Response::ok()
+            }
+            Request::Resume => {
+                self.set_paused(PauseReason::Request, false);
+                Response::ok()
+            }
+            Request::Subscribe => {
+                Response::error("Subscriptions are only available on the control socket")
+            }
+        }
+    }
+
+    fn setup_receiver(state: Rc<RefCell<Self>>, mut rx: mpsc::Receiver<ipc::server::Message>) {
+        glib::spawn_future_local(async move {
+            while let Some((request, tx)) = rx.recv().await {
+                let response = state.borrow_mut().handle(request);
+                // The client may already be gone.
+                let _ = tx.send(response);
+            }
+        });
+    }
+
+    /// Pause or resume all modules and sources.
+    ///
+    /// Updates stay paused as long as there is any reason left, and are refreshed
+    /// right away once resumed.
+    pub fn set_paused(&mut self, reason: PauseReason, paused: bool) {
+        let was_paused = self.is_paused();
+        if paused {
+            self.paused.insert(reason);
+        } else {
+            self.paused.remove(&reason);
+        }
+
+        let paused = self.is_paused();
+        if paused == was_paused {
+            return;
+        }
+
+        debug!("{} updates", if paused { "Pausing" } else { "Resuming" });
+        RBar::sources().set_paused(paused);
+        for bar in &mut self.bars {
+            bar.set_paused(paused);
+        }
+    }
+
+    fn is_paused(&self) -> bool {
+        !self.paused.is_empty()
+    }
+
+    /// Reload the configuration and rebuild all bars.
+    fn reload_config(&mut self) -> crate::Result<()> {
+        let rbar = Arc::new(RBar::new()?);
+
+        // Create the new bars first, so the application never runs out of windows.
+        let mut bars = load_bars(rbar.clone(), &self.app)?;
+        for bar in &mut bars {
+            bar.set_paused(self.is_paused());
+        }
+        for bar in std::mem::replace(&mut self.bars, bars) {
+            bar.close();
+        }
+
+        self.rbar = rbar;
+
+        Ok(())
+    }
+
+    fn bar(&self, output: &str) -> Option<&Bar> {
+        self.bars.iter().find(|bar| bar.output() == output)
+    }
+
+    /// Get the modules of all bars.
+    pub fn modules(&self) -> impl Iterator<Item = &ModuleHandle> {
+        self.bars.iter().flat_map(|bar| bar.modules())
+    }
+
+    /// Find a module by its unique id or by the `id` of its configuration.
+    ///
+    /// A configured id matches the module on the first bar showing it.
+    fn module(&self, id: &str) -> Option<&ModuleHandle> {
+        self.modules()
+            .find(|module| module.id.to_string() == id)
+            .or_else(|| {
+                self.modules()
+                    .find(|module| module.config_id.as_deref() == Some(id))
+            })
+    }
 }
 
 fn create_runtime() -> Runtime {
@@ -0,0 +1,217 @@
+//! Timers aligned to wall-clock boundaries.
+//!
+//! Modules showing the time register the period they need with [Scheduler::every]
+//! and tick exactly when the displayed value changes, e.g. at the start of every
+//! minute. Timers with the same period are shared, see [crate::sources].
+//!
+//! Ticks follow the wall clock: they fire right after a resume from suspend, when
+//! the system clock is set, and when the timezone changes. Timers stop while
+//! sources are paused.
+
+use std::{
+    io,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use gtk::{gio, prelude::*};
+use tokio::{io::unix::AsyncFd, sync::watch};
+use tracing::{debug, warn};
+
+use crate::{
+    rbar::RBar,
+    sources::{Publisher, Subscription},
+};
+
+/// [Scheduler] runs shared wall-clock timers. Get it with [RBar::scheduler].
+pub struct Scheduler {
+    timezone: watch::Sender<()>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self {
+            timezone: watch::channel(()).0,
+        }
+    }
+
+    /// Tick at every multiple of `period` since the epoch, e.g. at the start of
+    /// every minute for `60s`. The first tick is sent right away.
+    pub fn every(&'static self, period: Duration) -> Subscription<()> {
+        let period = period.as_secs().max(1);
+
+        RBar::sources().subscribe(format!("every {}s", period), move |publisher| {
+            self.tick(period, publisher)
+        })
+    }
+
+    async fn tick(&self, period: u64, publisher: Publisher<()>) {
+        let timer = WallTimer::new()
+            .inspect_err(|e| warn!("Failed to create timer, ticks may drift: {}", e))
+            .ok();
+        let mut timezone = self.timezone.subscribe();
+
+        loop {
+            publisher.send(());
+
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            let deadline = next_tick(now, period);
+
+            let wait = async {
+                let timer = match &timer {
+                    Some(timer) => timer.wait_until(deadline).await,
+                    None => Err(io::ErrorKind::Unsupported.into()),
+                };
+
+                if timer.is_err() {
+                    tokio::time::sleep(Duration::from_secs(deadline) - now).await;
+                }
+            };
+
+            tokio::select! {
+                _ = wait => {}
+                _ = publisher.refreshed() => {}
+                _ = publisher.paused() => {}
+                _ = timezone.changed() => {}
+            }
+
+            // Tick right away once resumed.
+            publisher.running().await;
+        }
+    }
+
+    /// Tick all timers right away when the timezone changes.
+    ///
+    /// The returned monitor has to be kept alive.
+    pub fn watch_timezone(&'static self) -> Option<gio::FileMonitor> {
+        let file = gio::File::for_path("/etc/localtime");
+        let monitor = file
+            .monitor_file(gio::FileMonitorFlags::NONE, gio::Cancellable::NONE)
+            .inspect_err(|e| warn!("Failed to watch timezone: {}", e))
+            .ok()?;
+
+        monitor.connect_changed(move |_, _, _, _| {
+            debug!("Timezone changed");
+            self.timezone.send_replace(());
+        });
+
+        Some(monitor)
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Seconds since the epoch of the first multiple of `period` after `now`.
+fn next_tick(now: Duration, period: u64) -> u64 {
+    (now.as_secs() / period + 1) * period
+}
+
+/// [WallTimer] waits for a point in time of the realtime clock.
+///
+/// Unlike [tokio::time::sleep] it keeps counting while the system is
+/// suspended and wakes up if the clock is set.
+struct WallTimer {
+    fd: AsyncFd<OwnedFd>,
+}
+
+impl WallTimer {
+    fn new() -> io::Result<Self> {
+        let fd = unsafe {
+            libc::timerfd_create(libc::CLOCK_REALTIME, libc::TFD_NONBLOCK | libc::TFD_CLOEXEC)
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // Safety: the file descriptor was just created and is owned by nobody else.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        Ok(Self {
+            fd: AsyncFd::new(fd)?,
+        })
+    }
+
+    /// Wait until `deadline` in seconds since the epoch, or until the clock is set.
+    async fn wait_until(&self, deadline: u64) -> io::Result<()> {
+        let spec = libc::itimerspec {
+            it_interval: libc::timespec {
+                tv_sec: 0,
+                tv_nsec: 0,
+            },
+            it_value: libc::timespec {
+                tv_sec: deadline as libc::time_t,
+                tv_nsec: 0,
+            },
+        };
+
+        let flags = libc::TFD_TIMER_ABSTIME | libc::TFD_TIMER_CANCEL_ON_SET;
+        let result = unsafe {
+            libc::timerfd_settime(self.fd.as_raw_fd(), flags, &spec, std::ptr::null_mut())
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        loop {
+            let mut guard = self.fd.readable().await?;
+
+            let read = guard.try_io(|fd| {
+                let mut expirations = [0u8; 8];
+                let read = unsafe {
+                    libc::read(
+                        fd.as_raw_fd(),
+                        expirations.as_mut_ptr().cast(),
+                        expirations.len(),
+                    )
+                };
+
+                match read {
+                    read if read >= 0 => Ok(()),
+                    _ => Err(io::Error::last_os_error()),
+                }
+            });
+
+            match read {
+                Ok(Ok(())) => return Ok(()),
+                // The clock was set.
+                Ok(Err(e)) if e.raw_os_error() == Some(libc::ECANCELED) => return Ok(()),
+                Ok(Err(e)) => return Err(e),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn next_tick_is_aligned() {
+        let at = |secs: u64, millis: u64| Duration::from_secs(secs) + Duration::from_millis(millis);
+
+        assert_eq!(next_tick(at(120, 0), 60), 180);
+        assert_eq!(next_tick(at(120, 1), 60), 180);
+        assert_eq!(next_tick(at(179, 999), 60), 180);
+        assert_eq!(next_tick(at(0, 500), 1), 1);
+        assert_eq!(next_tick(at(7199, 0), 3600), 7200);
+    }
+
+    #[tokio::test]
+    async fn wall_timer_waits_for_deadline() {
+        let timer = WallTimer::new().unwrap();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let deadline = next_tick(now, 1);
+
+        timer.wait_until(deadline).await.unwrap();
+
+        let woke = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        assert!(woke >= Duration::from_secs(deadline));
+        assert!(woke < Duration::from_secs(deadline + 1));
+    }
+}
@@ -0,0 +1,192 @@
+//! Data sources shared by all modules.
+//!
+//! A source, like a battery or a command, runs once no matter how many modules
+//! on how many bars subscribe to it, and fans its values out to all of them.
+//! Sources are keyed by the type of their values and their parameters. A source
+//! stops once its last [Subscription] is dropped.
+//!
+//! All sources are paused while the system sleeps or the screens are off, see
+//! [Sources::set_paused].
+
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::sync::{watch, Notify};
+use tracing::debug;
+
+use crate::rbar::RBar;
+
+type Key = (TypeId, String);
+
+/// [Sources] is the registry of running sources. Get it with [RBar::sources].
+pub struct Sources {
+    entries: Mutex<HashMap<Key, Box<dyn Any + Send>>>,
+    paused: watch::Sender<bool>,
+}
+
+struct Shared<T> {
+    tx: watch::Sender<Option<T>>,
+    refresh: Arc<Notify>,
+}
+
+impl Sources {
+    pub fn new() -> Self {
+        Self {
+            entries: Default::default(),
+            paused: watch::channel(false).0,
+        }
+    }
+
+    /// Pause or resume all sources. Sources update right away once resumed.
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.send_if_modified(|current| {
+            let modified = *current != paused;
+            *current = paused;
+            modified
+        });
+    }
+
+    /// Subscribe to the source with the given key, starting it with `run` if it
+    /// is not running yet.
+    ///
+    /// `run` may be called again if the source got subscribed to while stopping.
+    pub fn subscribe<T, F, Fut>(&'static self, key: impl Into<String>, run: F) -> Subscription<T>
+    where
+        T: Clone + Send + Sync + 'static,
+        F: Fn(Publisher<T>) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let key = (TypeId::of::<T>(), key.into());
+        let mut entries = self.entries.lock().unwrap();
+
+        if let Some(shared) = entries
+            .get(&key)
+            .and_then(|shared| shared.downcast_ref::<Arc<Shared<T>>>())
+        {
+            return Subscription::new(shared);
+        }
+
+        debug!("Starting source '{}'", key.1);
+
+        let shared = Arc::new(Shared {
+            tx: watch::channel(None).0,
+            refresh: Arc::new(Notify::new()),
+        });
+        entries.insert(key.clone(), Box::new(shared.clone()));
+
+        // Subscribe before the source starts, so it isn't stopped right away.
+        let subscription = Subscription::new(&shared);
+
+        RBar::runtime().spawn(async move {
+            loop {
+                let publisher = Publisher {
+                    shared: shared.clone(),
+                    paused: self.paused.subscribe(),
+                };
+                let finished = tokio::select! {
+                    _ = run(publisher) => true,
+                    _ = shared.tx.closed() => false,
+                };
+
+                let mut entries = self.entries.lock().unwrap();
+                if finished || shared.tx.receiver_count() == 0 {
+                    debug!("Stopping source '{}'", key.1);
+                    entries.remove(&key);
+                    break;
+                }
+
+                // Subscribed to again while stopping.
+            }
+        });
+
+        subscription
+    }
+}
+
+impl Default for Sources {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// [Subscription] receives the values of a source.
+pub struct Subscription<T> {
+    rx: watch::Receiver<Option<T>>,
+    refresh: Arc<Notify>,
+}
+
+impl<T: Clone> Subscription<T> {
+    fn new(shared: &Shared<T>) -> Self {
+        let mut rx = shared.tx.subscribe();
+        // Receive the latest value of an already running source right away.
+        rx.mark_changed();
+
+        Self {
+            rx,
+            refresh: shared.refresh.clone(),
+        }
+    }
+
+    /// Wait for the next value. Returns `None` once the source ended.
+    pub async fn next(&mut self) -> Option<T> {
+        loop {
+            self.rx.changed().await.ok()?;
+            if let Some(value) = self.rx.borrow_and_update().clone() {
+                return Some(value);
+            }
+        }
+    }
+
+    /// Ask the source to update early, for all subscribers.
+    pub fn refresh(&self) {
+        self.refresh.notify_one();
+    }
+}
+
+/// [Publisher] is used by a source to send its values.
+pub struct Publisher<T> {
+    shared: Arc<Shared<T>>,
+    paused: watch::Receiver<bool>,
+}
+
+impl<T> Publisher<T> {
+    /// Send a value to all subscribers.
+    pub fn send(&self, value: T) {
+        self.shared.tx.send_replace(Some(value));
+    }
+
+    /// Wait until a subscriber asks for a refresh.
+    pub async fn refreshed(&self) {
+        self.shared.refresh.notified().await;
+    }
+
+    /// Wait until sources get paused.
+    pub async fn paused(&self) {
+        // The sender is static.
+        let _ = self.paused.clone().wait_for(|paused| *paused).await;
+    }
+
+    /// Wait until sources are not paused.
+    pub async fn running(&self) {
+        let _ = self.paused.clone().wait_for(|paused| !*paused).await;
+    }
+
+    /// Sleep for the duration, or until a subscriber asks for a refresh.
+    /// Returns `true` if it was woken up by a refresh.
+    ///
+    /// While sources are paused, this only returns once they are resumed.
+    pub async fn sleep(&self, duration: Duration) -> bool {
+        let refreshed = tokio::select! {
+            _ = tokio::time::sleep(duration) => false,
+            _ = self.refreshed() => true,
+            _ = self.paused() => false,
+        };
+        self.running().await;
+        refreshed
+    }
+}
@@ -5,25 +5,16 @@ use tracing::{debug, warn};
 
 use crate::config::Config;
 
-pub fn init() {
-    let path = Config::get_style_path();
-    if !path.exists() {
-        warn!("Style file does not exist: {}", path.display());
-    }
-    load_css(path);
-}
-
-fn load_css(path: PathBuf) {
+/// Load the user style sheet and register it for the default display.
+pub fn init() -> CssProvider {
     let provider = CssProvider::new();
-
-    provider.load_from_file(&File::for_path(&path));
-    debug!("Loaded css from '{}'", path.display());
+    reload(&provider);
 
     let screen = match Display::default() {
         Some(display) => display,
         None => {
             warn!("Failed to get default display");
-            return;
+            return provider;
         }
     };
 
@@ -32,4 +23,20 @@ fn load_css(path: PathBuf) {
         &provider,
         GTK_STYLE_PROVIDER_PRIORITY_USER as u32,
     );
+
+    provider
+}
+
+/// Reload the user style sheet into an existing provider.
+pub fn reload(provider: &CssProvider) {
+    let path = Config::get_style_path();
+    if !path.exists() {
+        warn!("Style file does not exist: {}", path.display());
+    }
+    load_css(provider, path);
+}
+
+fn load_css(provider: &CssProvider, path: PathBuf) {
+    provider.load_from_file(&File::for_path(&path));
+    debug!("Loaded css from '{}'", path.display());
 }
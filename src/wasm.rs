@@ -0,0 +1,320 @@
+//! Sandboxed WebAssembly modules.
+//!
+//! A module is a core WebAssembly module run by an interpreter, without WASI.
+//! It can only reach the outside world through the host functions below, and
+//! those only grant what its [Capabilities] allow. Every call is limited in
+//! memory and instructions, so a broken module can't take rbar down with it.
+//!
+//! The module exports:
+//!
+//! - `memory`
+//! - `alloc(len: i32) -> i32`, returning memory for the host to write to
+//! - `handle(ptr: i32, len: i32) -> i64`, receiving an event as JSON and returning
+//!   its output as `ptr << 32 | len`, or `0` for no output
+//!
+//! Events are `{"event": "init", "options": {...}}` once, then `{"event": "tick"}`
+//! every interval and `{"event": "refresh"}` on the `refresh` action. The output is
+//! the same as for custom modules, e.g. `{"text": "21°C", "class": "warm"}`.
+//!
+//! The host provides in the `rbar` namespace:
+//!
+//! - `log(ptr: i32, len: i32)`
+//! - `read_file(ptr: i32, len: i32) -> i64`, reading an allowed file
+//! - `run(ptr: i32, len: i32) -> i64`, running an allowed command given as a JSON
+//!   array of arguments, returning its stdout. Commands are killed after 5 seconds.
+//!
+//! Results are returned like outputs, or `-1` if denied or failed.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Read,
+    os::unix::{fs::OpenOptionsExt, io::AsRawFd},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use tracing::{debug, warn};
+use wasmi::{
+    AsContext, Caller, Config, Engine, Extern, Instance, Linker, Memory, Module, Store,
+    StoreLimits, StoreLimitsBuilder, TypedFunc,
+};
+
+/// Instructions a module may run per event.
+const FUEL: u64 = 100_000_000;
+
+/// Memory a module may use.
+const MEMORY: usize = 16 * 1024 * 1024;
+
+/// Time a command run by a module may take. Fuel doesn't count time spent in the host.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// [Capabilities] granted to a module in its configuration.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Capabilities {
+    /// Files the module may read. Directories allow all files below them.
+    pub files: Vec<PathBuf>,
+    /// Programs the module may run, matched against the first argument.
+    pub commands: Vec<String>,
+}
+
+impl Capabilities {
+    /// Open a file the module may read.
+    ///
+    /// The path of the opened file is checked, not the given one, so a symlink
+    /// changed in between can't point the read elsewhere. Opening doesn't block
+    /// on FIFOs, and only regular files are returned.
+    fn readable(&self, path: &Path) -> Option<File> {
+        let file = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK | libc::O_NOCTTY)
+            .open(path)
+            .ok()?;
+        let opened = fs::read_link(format!("/proc/self/fd/{}", file.as_raw_fd())).ok()?;
+
+        let allowed = self
+            .files
+            .iter()
+            .filter_map(|allowed| allowed.canonicalize().ok())
+            .any(|allowed| opened.starts_with(allowed));
+
+        (allowed && file.metadata().ok()?.is_file()).then_some(file)
+    }
+
+    fn can_run(&self, program: &str) -> bool {
+        self.commands.iter().any(|allowed| allowed == program)
+    }
+}
+
+struct State {
+    capabilities: Capabilities,
+    limits: StoreLimits,
+    name: String,
+}
+
+/// [Runtime] is an instantiated WebAssembly module.
+pub struct Runtime {
+    store: Store<State>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    handle: TypedFunc<(i32, i32), i64>,
+}
+
+impl Runtime {
+    /// Load the module at `path` and send it the `init` event.
+    pub fn new(
+        path: &Path,
+        capabilities: Capabilities,
+        options: &Map<String, Value>,
+    ) -> crate::Result<(Self, Option<String>)> {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+
+        let bytes = fs::read(path).map_err(|e| format!("Failed to read module: {}", e))?;
+        let module = Module::new(&engine, &bytes[..])?;
+
+        let mut store = Store::new(
+            &engine,
+            State {
+                capabilities,
+                limits: StoreLimitsBuilder::new().memory_size(MEMORY).build(),
+                name: path.display().to_string(),
+            },
+        );
+        store.limiter(|state| &mut state.limits);
+        store.set_fuel(FUEL).map_err(wasmi::Error::from)?;
+
+        let mut linker = Linker::new(&engine);
+        linker.func_wrap("rbar", "log", log)?;
+        linker.func_wrap("rbar", "read_file", read_file)?;
+        linker.func_wrap("rbar", "run", run)?;
+
+        let instance = linker.instantiate(&mut store, &module)?.start(&mut store)?;
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or("Module exports no memory")?;
+        let alloc = typed(&instance, &store, "alloc")?;
+        let handle = typed(&instance, &store, "handle")?;
+
+        let mut runtime = Self {
+            store,
+            memory,
+            alloc,
+            handle,
+        };
+        let output = runtime.handle(&json!({ "event": "init", "options": options }))?;
+
+        Ok((runtime, output))
+    }
+
+    /// Send an event to the module and return its output.
+    pub fn handle(&mut self, event: &Value) -> crate::Result<Option<String>> {
+        self.store.set_fuel(FUEL).map_err(wasmi::Error::from)?;
+
+        let event = event.to_string();
+        let ptr = self.alloc.call(&mut self.store, event.len() as i32)?;
+        self.memory
+            .write(&mut self.store, ptr as usize, event.as_bytes())
+            .map_err(wasmi::Error::from)?;
+
+        let result = self
+            .handle
+            .call(&mut self.store, (ptr, event.len() as i32))?;
+
+        if result == 0 {
+            return Ok(None);
+        }
+        Ok(Some(read(&self.store, self.memory, result)?))
+    }
+}
+
+fn typed<P, R>(
+    instance: &Instance,
+    store: impl AsContext,
+    name: &str,
+) -> crate::Result<TypedFunc<P, R>>
+where
+    P: wasmi::WasmParams,
+    R: wasmi::WasmResults,
+{
+    Ok(instance
+        .get_typed_func(store, name)
+        .map_err(|e| format!("Invalid export '{}': {}", name, e))?)
+}
+
+/// Copy a string returned as `ptr << 32 | len` out of the module.
+///
+/// The range is checked against the memory before copying, the length is up to the module.
+fn read(store: impl AsContext<Data = State>, memory: Memory, result: i64) -> crate::Result<String> {
+    let (ptr, len) = ((result >> 32) as u32 as usize, result as u32 as usize);
+
+    let bytes = ptr
+        .checked_add(len)
+        .and_then(|end| memory.data(store.as_context()).get(ptr..end))
+        .ok_or("String out of bounds")?;
+    Ok(String::from_utf8(bytes.to_vec())?)
+}
+
+/// Read a string argument of a host function.
+fn argument(caller: &Caller<State>, ptr: i32, len: i32) -> Option<(Memory, String)> {
+    let memory = caller.get_export("memory").and_then(Extern::into_memory)?;
+    let result = (i64::from(ptr) << 32) | i64::from(len as u32);
+    Some((memory, read(caller, memory, result).ok()?))
+}
+
+/// Return a result to the module, `-1` on error.
+fn respond(caller: &mut Caller<State>, memory: Memory, result: Result<Vec<u8>, String>) -> i64 {
+    let bytes = match result {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            warn!("{}: {}", caller.data().name, e);
+            return -1;
+        }
+    };
+
+    let Some(alloc) = caller.get_export("alloc").and_then(Extern::into_func) else {
+        return -1;
+    };
+    let Ok(alloc) = alloc.typed::<i32, i32>(&*caller) else {
+        return -1;
+    };
+
+    let Ok(ptr) = alloc.call(&mut *caller, bytes.len() as i32) else {
+        return -1;
+    };
+    if memory.write(&mut *caller, ptr as usize, &bytes).is_err() {
+        return -1;
+    }
+
+    (i64::from(ptr) << 32) | bytes.len() as i64
+}
+
+fn log(caller: Caller<State>, ptr: i32, len: i32) {
+    if let Some((_, message)) = argument(&caller, ptr, len) {
+        debug!("{}: {}", caller.data().name, message);
+    }
+}
+
+fn read_file(mut caller: Caller<State>, ptr: i32, len: i32) -> i64 {
+    let Some((memory, path)) = argument(&caller, ptr, len) else {
+        return -1;
+    };
+
+    let result = match caller.data().capabilities.readable(Path::new(&path)) {
+        Some(mut file) => {
+            let mut bytes = Vec::new();
+            file.read_to_end(&mut bytes)
+                .map(|_| bytes)
+                .map_err(|e| format!("Failed to read '{}': {}", path, e))
+        }
+        None => Err(format!("Not allowed to read '{}'", path)),
+    };
+
+    respond(&mut caller, memory, result)
+}
+
+fn run(mut caller: Caller<State>, ptr: i32, len: i32) -> i64 {
+    let Some((memory, args)) = argument(&caller, ptr, len) else {
+        return -1;
+    };
+
+    let result = match serde_json::from_str::<Vec<String>>(&args) {
+        Ok(args) => match args.split_first() {
+            Some((program, args)) if caller.data().capabilities.can_run(program) => {
+                output(program, args)
+            }
+            Some((program, _)) => Err(format!("Not allowed to run '{}'", program)),
+            None => Err("No command given".to_string()),
+        },
+        Err(e) => Err(format!("Invalid command: {}", e)),
+    };
+
+    respond(&mut caller, memory, result)
+}
+
+/// Run a command and return its stdout, killing it after [TIMEOUT].
+fn output(program: &str, args: &[String]) -> Result<Vec<u8>, String> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("Failed to run '{}': {}", program, e))?;
+
+    // Read while waiting, a full pipe would block the command.
+    let mut stdout = child.stdout.take().ok_or("No stdout")?;
+    let reader = thread::spawn(move || {
+        let mut bytes = Vec::new();
+        stdout.read_to_end(&mut bytes).map(|_| bytes)
+    });
+
+    let deadline = Instant::now() + TIMEOUT;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if Instant::now() < deadline => thread::sleep(Duration::from_millis(10)),
+            Ok(None) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("'{}' timed out after {:?}", program, TIMEOUT));
+            }
+            Err(e) => return Err(format!("Failed to wait for '{}': {}", program, e)),
+        }
+    };
+
+    let stdout = reader
+        .join()
+        .map_err(|_| format!("Failed to read output of '{}'", program))?
+        .map_err(|e| format!("Failed to read output of '{}': {}", program, e))?;
+
+    if !status.success() {
+        return Err(format!("'{}' exited with {}", program, status));
+    }
+    Ok(stdout)
+}